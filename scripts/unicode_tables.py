@@ -0,0 +1,45 @@
+#!/usr/bin/env python3
+"""Generate src/unicode_tables.rs, the general category table used by \\p{..} in regexes.
+
+Run from the repository root:
+    python3 scripts/unicode_tables.py > src/unicode_tables.rs
+"""
+import sys
+import unicodedata
+
+# Unassigned code points (Cn) are left out, and surrogates (Cs) aren't chars in Rust
+SKIPPED = {"Cn", "Cs"}
+
+
+def category_ranges():
+    categories = {}
+    for code in range(sys.maxunicode + 1):
+        category = unicodedata.category(chr(code))
+        if category in SKIPPED:
+            continue
+        ranges = categories.setdefault(category, [])
+        if ranges and ranges[-1][1] == code - 1:
+            ranges[-1][1] = code
+        else:
+            ranges.append([code, code])
+    return categories
+
+
+def main():
+    categories = category_ranges()
+    print("// Generated by scripts/unicode_tables.py from the Unicode Character Database "
+          f"{unicodedata.unidata_version}, don't edit by hand.")
+    print()
+    print("// The two letter general categories with the ranges of chars in each of them, sorted by name")
+    print("pub(crate) const GENERAL_CATEGORIES: &[(&str, &[(char, char)])] = &[")
+    for name in sorted(categories):
+        print(f"    (\"{name}\", &[")
+        ranges = [f"('\\u{{{start:X}}}', '\\u{{{end:X}}}')" for start, end in categories[name]]
+        for i in range(0, len(ranges), 6):
+            print("        " + ", ".join(ranges[i:i + 6]) + ",")
+        print("    ]),")
+    print("];")
+
+
+if __name__ == "__main__":
+    main()
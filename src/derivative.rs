@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+
+use crate::{dfa::Dfa, error::RegexError, nfa::RegexOptions, regex::{ParsedRegex, parse_with_alphabet, parse_with_inferred_alphabet, Regex}};
+
+impl Regex {
+    /*
+    The Brzozowski derivative of the regex by a symbol: the regex matching the strings w such that the symbol followed by w is matched.
+    The result is built with the simplifying constructors, so derivatives that are equal up to the identities of Regex::simplified
+    come out equal, and repeatedly taking derivatives only produces finitely many distinct regexes.
+    The regex should already be simplified.
+    */
+    pub fn derivative(&self, symbol: char) -> Regex {
+        match self {
+            Regex::Empty | Regex::Epsilon => Regex::Empty,
+            Regex::Literal(literal) => if *literal == symbol { Regex::Epsilon } else { Regex::Empty },
+            Regex::Class(class) => if class.contains(symbol) { Regex::Epsilon } else { Regex::Empty },
+            Regex::Concat(items) => {
+                let Some((first, rest)) = items.split_first() else { return Regex::Empty };
+                let rest = Self::concat(rest.to_vec());
+                let derivative = Self::concat(vec![first.derivative(symbol), rest.clone()]);
+                // when the first item can match nothing, the symbol may be read by the rest
+                if first.is_nullable() {
+                    Self::alt(vec![derivative, rest.derivative(symbol)])
+                } else {
+                    derivative
+                }
+            },
+            Regex::Alt(items) => Self::alt(items.iter().map(|item| item.derivative(symbol)).collect()),
+            Regex::Star(inner) => Self::concat(vec![inner.derivative(symbol), self.clone()]),
+            Regex::Plus(inner) => Self::concat(vec![inner.derivative(symbol), Self::star((**inner).clone())]),
+            Regex::Optional(inner) => inner.derivative(symbol),
+            Regex::Repeat { max: Some(0), .. } => Regex::Empty,
+            Regex::Repeat { inner, min, max } => Self::concat(vec![
+                inner.derivative(symbol),
+                Self::repeat((**inner).clone(), min.saturating_sub(1), max.map(|max| max - 1)),
+            ]),
+            Regex::Intersect(parts) => Self::intersect(parts.iter().map(|part| part.derivative(symbol)).collect()),
+            Regex::Complement(inner) => Self::complement(inner.derivative(symbol)),
+        }
+    }
+}
+
+impl Dfa {
+    pub fn from_regex_derivatives(regex: &str, alphabet: &str, mark_num: usize) -> Result<Self, RegexError> {
+        Self::from_regex_derivatives_with_options(regex, alphabet, mark_num, &RegexOptions::default())
+    }
+
+    pub fn from_regex_derivatives_with_options(regex: &str, alphabet: &str, mark_num: usize, options: &RegexOptions) -> Result<Self, RegexError> {
+        let ParsedRegex { tree, alphabet, .. } = parse_with_alphabet(regex, alphabet, options)?;
+        Ok(Self::from_tree_derivatives(&tree, &alphabet, mark_num))
+    }
+
+    pub fn from_regex_derivatives_inferred(regex: &str, extra_symbols: &str, mark_num: usize) -> Result<Self, RegexError> {
+        Self::from_regex_derivatives_inferred_with_options(regex, extra_symbols, mark_num, &RegexOptions::default())
+    }
+
+    pub fn from_regex_derivatives_inferred_with_options(regex: &str, extra_symbols: &str, mark_num: usize, options: &RegexOptions) -> Result<Self, RegexError> {
+        let ParsedRegex { tree, alphabet, .. } = parse_with_inferred_alphabet(regex, extra_symbols, options)?;
+        Ok(Self::from_tree_derivatives(&tree, &alphabet, mark_num))
+    }
+
+    /*
+    Build a DFA directly from a regex, without going through an NFA.
+    Each state is a regex, starting with the simplified regex itself,
+    and reading a symbol moves to the derivative of the state by that symbol.
+    States whose regex matches the empty string are accepting.
+
+    Equal states are merged, which usually gives a DFA close to the minimal one, but it isn't minimized:
+    call Dfa::minimized for that. Building the DFA this way doesn't share any code with the NFA pipeline,
+    so it can be used to cross-check Dfa::from_regex with Dfa::equivalent.
+    */
+    pub fn from_tree_derivatives(tree: &Regex, alphabet: &[char], mark_num: usize) -> Self {
+        let start = tree.simplified();
+        let mut state_indices: HashMap<Regex, usize> = HashMap::from([(start.clone(), 0)]);
+        let mut states = vec![start];
+        let mut transition: Vec<Vec<usize>> = vec![];
+        let mut marks = vec![];
+
+        let mut current = 0;
+        while current < states.len() {
+            let state = states[current].clone();
+            marks.push(if state.is_nullable() { mark_num } else { 0 });
+
+            let row = alphabet.iter().map(|&symbol| {
+                let next = state.derivative(symbol);
+                *state_indices.entry(next.clone()).or_insert_with(|| {
+                    states.push(next);
+                    states.len() - 1
+                })
+            }).collect();
+            transition.push(row);
+            current += 1;
+        }
+
+        Self::from_table(marks, transition, alphabet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::nfa::Nfa;
+
+    use super::*;
+
+    // patterns with the extra symbols their negated classes, wildcards and complements are taken over
+    const PATTERNS: &[(&str, &str)] = &[
+        ("a", ""),
+        (r"\e", "a"),
+        ("(a|b)*abb", ""),
+        ("a?b+c*", ""),
+        ("(ab|ba){2,3}", ""),
+        ("[a-c]x[^a]", "d"),
+        (".*a.*", "bc"),
+        ("(a|b)*a(a|b)(a|b)(a|b)", ""),
+        ("(a|b)*&~(.*aa.*)", ""),
+        ("~(a*)", "b"),
+        ("~~(ab)", ""),
+        ("~(a|b)", "c"),
+        ("(a|b)*b&(a|b)*a(a|b)", ""),
+        ("(.*ab.*)&(.*ba.*)&~(.*bb.*)", ""),
+        ("~((a|b)*a)&c*|a", ""),
+    ];
+
+    #[test]
+    fn derivatives_agree_with_subset_construction() {
+        for &(pattern, extra_symbols) in PATTERNS {
+            let from_derivatives = Dfa::from_regex_derivatives_inferred(pattern, extra_symbols, 1).unwrap();
+            let from_nfa = Dfa::from_nfa(&Nfa::from_regex_inferred(pattern, extra_symbols, 1).unwrap()).minimized();
+            assert_eq!(from_derivatives.distinguishing_string(&from_nfa), None, "{pattern}");
+            assert_eq!(from_derivatives.minimized().marks.len(), from_nfa.marks.len(), "{pattern}");
+        }
+    }
+
+    #[test]
+    fn derivatives_agree_with_subset_construction_over_an_alphabet() {
+        for &(pattern, alphabet) in &[("a*b", "abc"), ("[^a]+", "abc"), ("~(ab)&.+", "ab"), ("(ab)*&~(abab)", "abc")] {
+            let from_derivatives = Dfa::from_regex_derivatives(pattern, alphabet, 2).unwrap();
+            let from_nfa = Dfa::from_nfa(&Nfa::from_regex(pattern, alphabet, 2).unwrap()).minimized();
+            assert_eq!(from_derivatives.distinguishing_string(&from_nfa), None, "{pattern}");
+        }
+    }
+}
@@ -1,7 +1,7 @@
-use std::{collections::{HashMap, VecDeque}, hash::Hash, path::Display, thread::current};
-use itertools::{enumerate, GroupingMapBy, Itertools};
+use std::{collections::{HashMap, VecDeque}, fmt};
+use itertools::Itertools;
 
-use crate::nfa::Nfa;
+use crate::{error::RegexError, nfa::{Label, Nfa, RegexOptions}, regex::{LINE_END, LINE_START, TEXT_END}, utf8::byte_symbol};
 /*
 A Deterministic Finite Automaton is a simple machine model that will recognize regular languages
 A DFA consists of a 5-tuple (States, Alphabet, Initial, Transition, Accepting)
@@ -61,15 +61,27 @@ impl Dfa {
         }
     }
 
-    pub fn from_regex(regex:&str, alphabet:&str, mark_num:usize) -> Self {
-        Self::from_nfa(&Nfa::from_regex(regex, alphabet, mark_num))
+    pub fn from_regex(regex:&str, alphabet:&str, mark_num:usize) -> Result<Self, RegexError> {
+        Ok(Self::from_nfa(&Nfa::from_regex(regex, alphabet, mark_num)?))
+    }
+
+    pub fn from_regex_with_options(regex:&str, alphabet:&str, mark_num:usize, options: &RegexOptions) -> Result<Self, RegexError> {
+        Ok(Self::from_nfa(&Nfa::from_regex_with_options(regex, alphabet, mark_num, options)?))
+    }
+
+    pub fn from_regex_inferred(regex:&str, extra_symbols:&str, mark_num:usize) -> Result<Self, RegexError> {
+        Ok(Self::from_nfa(&Nfa::from_regex_inferred(regex, extra_symbols, mark_num)?))
+    }
+
+    pub fn from_regex_inferred_with_options(regex:&str, extra_symbols:&str, mark_num:usize, options: &RegexOptions) -> Result<Self, RegexError> {
+        Ok(Self::from_nfa(&Nfa::from_regex_inferred_with_options(regex, extra_symbols, mark_num, options)?))
     }
 
     pub fn from_nfa(nfa: &Nfa) -> Self {
         
-        let nfa_transitions:Vec<Vec<Option<Vec<usize>>>> = nfa.transition.clone();
         let alphabet_size = nfa.symbols_table.len();
-        let alphabet:&Vec<usize> = &(0..alphabet_size).collect();
+        // the sorted symbols with their columns, so the symbols matched by a label are found by binary search
+        let alphabet = nfa.alphabet();
 
         let mut subset_table: HashMap<Vec<usize>, usize> = HashMap::new();
         let mut transition: Vec<Vec<usize>> = vec![vec![0; alphabet_size]];
@@ -84,22 +96,25 @@ impl Dfa {
         let mut stack: Vec<Vec<usize>> = vec![start_state];
 
         let mut state_count = 0;
-        while !stack.is_empty() {
-            let dfa_state = stack.pop().unwrap();
+        while let Some(dfa_state) = stack.pop() {
             let dfa_index = subset_table[&dfa_state];
-            for &symbol in alphabet{
-                let mut candidate: Vec<usize> = vec![];
-                for &nfa_state in &dfa_state {
-                    if let Some(range_states) = &nfa_transitions[nfa_state][symbol] {
-                        candidate = [candidate, range_states.clone()].concat()
+            let mut candidates: Vec<Vec<usize>> = vec![vec![]; alphabet_size];
+            for &nfa_state in &dfa_state {
+                for &(label, target) in &nfa.edges[nfa_state] {
+                    let Label::Range(start, end) = label else { continue };
+                    let first = alphabet.partition_point(|&(symbol, _)| symbol < start);
+                    for &(_, column) in alphabet[first..].iter().take_while(|&&(symbol, _)| symbol <= end) {
+                        candidates[column].push(target);
                     }
                 }
+            }
+
+            for (symbol, mut candidate) in candidates.into_iter().enumerate() {
                 candidate.sort();
                 candidate.dedup();
                 candidate = nfa.empty_closure(candidate).unwrap_or(vec![]);
 
-                if  subset_table.contains_key(&candidate) {
-                    let candidate_index = subset_table[&candidate];
+                if let Some(&candidate_index) = subset_table.get(&candidate) {
                     transition[dfa_index][symbol] = candidate_index;
                 } else {
                     state_count += 1;
@@ -116,8 +131,7 @@ impl Dfa {
 
 
         let dfa = Dfa{transition, symbol_indices:nfa.symbols_table.clone(), marks};
-        let minimized = dfa.minimized();
-        minimized
+        dfa.minimized()
     }
 
 
@@ -125,7 +139,7 @@ impl Dfa {
     pub fn minimized(&self) -> Self {
         let mut cloud = DfaCloud::from_dfa(self);
         cloud.divide();
-        let (transition, marks) = cloud.into_dfa_graph();
+        let (transition, marks) = cloud.to_dfa_graph();
 
 
         Self { marks, transition, symbol_indices: self.symbol_indices.clone()}
@@ -133,6 +147,120 @@ impl Dfa {
         
 
     
+    // Build a DFA from its marks and transition table, with the columns in the order of the alphabet
+    pub(crate) fn from_table(marks: Vec<usize>, transition: Vec<Vec<usize>>, alphabet: &[char]) -> Self {
+        let symbol_indices = alphabet.iter().enumerate().map(|(i, &symbol)| (symbol, i)).collect();
+        Dfa { marks, transition, symbol_indices }
+    }
+
+    /*
+    Run two DFAs side by side. The states of the product are pairs of states,
+    and the mark of a pair is given by combining the marks of its states.
+    The alphabet is the union of both alphabets, a symbol missing from one of them sends it to a dead state with mark 0.
+    The product isn't minimized.
+    */
+    fn product(&self, other: &Dfa, combine: impl Fn(usize, usize) -> usize) -> Dfa {
+        let alphabet = self.alphabet().into_iter().chain(other.alphabet()).unique().collect_vec();
+
+        // None stands for the dead state a DFA enters on a symbol outside of its alphabet
+        let step = |dfa: &Dfa, state: Option<usize>, symbol: char| {
+            state.and_then(|state| dfa.symbol_indices.get(&symbol).map(|&index| dfa.transition[state][index]))
+        };
+        let mark = |dfa: &Dfa, state: Option<usize>| state.map_or(0, |state| dfa.marks[state]);
+
+        let start = (Some(0), Some(0));
+        let mut pair_indices: HashMap<(Option<usize>, Option<usize>), usize> = HashMap::from([(start, 0)]);
+        let mut pairs = vec![start];
+        let mut transition: Vec<Vec<usize>> = vec![];
+        let mut marks = vec![];
+
+        let mut current = 0;
+        while current < pairs.len() {
+            let (a, b) = pairs[current];
+            marks.push(combine(mark(self, a), mark(other, b)));
+
+            let row = alphabet.iter().map(|&symbol| {
+                let next = (step(self, a, symbol), step(other, b, symbol));
+                *pair_indices.entry(next).or_insert_with(|| {
+                    pairs.push(next);
+                    pairs.len() - 1
+                })
+            }).collect_vec();
+            transition.push(row);
+            current += 1;
+        }
+
+        Self::from_table(marks, transition, &alphabet)
+    }
+
+    /*
+    Build a DFA accepting the strings accepted by both DFAs, over the union of their alphabets.
+    An accepting state of the result keeps the larger of the two marks.
+    */
+    pub fn intersection(&self, other: &Dfa) -> Dfa {
+        self.product(other, |a, b| if a > 0 && b > 0 { a.max(b) } else { 0 }).minimized()
+    }
+
+    /*
+    Find a shortest string, over the union of both alphabets, that the DFAs give different marks to.
+    There is none exactly when both DFAs recognize the same language with the same marks.
+    */
+    pub fn distinguishing_string(&self, other: &Dfa) -> Option<String> {
+        let product = self.product(other, |a, b| usize::from(a != b));
+        let alphabet = product.alphabet();
+
+        // breadth-first search from the start state, remembering how each state was first reached
+        let mut previous: Vec<Option<(usize, char)>> = vec![None; product.marks.len()];
+        let mut visited = vec![false; product.marks.len()];
+        let mut queue = VecDeque::from([0]);
+        visited[0] = true;
+        while let Some(state) = queue.pop_front() {
+            if product.marks[state] > 0 {
+                let mut word = vec![];
+                let mut current = state;
+                while let Some((parent, symbol)) = previous[current] {
+                    word.push(symbol);
+                    current = parent;
+                }
+                return Some(word.into_iter().rev().collect())
+            }
+            for (&next, &symbol) in product.transition[state].iter().zip(&alphabet) {
+                if !visited[next] {
+                    visited[next] = true;
+                    previous[next] = Some((state, symbol));
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        None
+    }
+
+    pub fn equivalent(&self, other: &Dfa) -> bool {
+        self.distinguishing_string(other).is_none()
+    }
+
+    /*
+    Build a DFA accepting exactly the strings over the same alphabet that this DFA rejects.
+    Accepting states of the complement have mark 1.
+    */
+    pub fn complement(&self) -> Dfa {
+        let marks = self.marks.iter().map(|&mark| usize::from(mark == 0)).collect();
+        Dfa { marks, transition: self.transition.clone(), symbol_indices: self.symbol_indices.clone() }.minimized()
+    }
+
+    /*
+    Build a DFA accepting the reverses of the strings this DFA accepts, over the same alphabet, see Nfa::reverse.
+    */
+    pub fn reverse(&self) -> Dfa {
+        Dfa::from_nfa(&Nfa::from(self).reverse())
+    }
+
+    // The symbols of the alphabet in the order of the transition columns
+    pub fn alphabet(&self) -> Vec<char> {
+        self.symbol_indices.keys().copied().sorted_by_key(|x| self.symbol_indices[x]).collect()
+    }
+
     pub fn accepts(&self, word: String) -> bool {
         let mut state = 0;
 
@@ -144,33 +272,99 @@ impl Dfa {
         self.marks[state] > 0
     }
 
+    /*
+    Whether the DFA accepts the word, or the word followed by the virtual symbols of the anchors that hold after it:
+    LINE_END if a newline or the end of the text follows, and TEXT_END after it at the end of the text.
+    */
+    pub(crate) fn accepts_before(&self, word: String, line_end: bool, text_end: bool) -> bool {
+        let with_line_end = word.clone() + &LINE_END.to_string();
+        self.accepts(word)
+            || line_end && self.accepts(with_line_end.clone())
+            || text_end && self.accepts(with_line_end + &TEXT_END.to_string())
+    }
+
+    // Run a DFA in UTF-8 mode, see RegexOptions::utf8, on a string of bytes
+    pub fn accepts_bytes(&self, word: &[u8]) -> bool {
+        let mut state = 0;
+
+        for &byte in word {
+            let Some(&symbol_index) = self.symbol_indices.get(&byte_symbol(byte)) else { return false };
+            state = self.transition[state][symbol_index];
+        }
+
+        self.marks[state] > 0
+    }
+
+    /*
+    A state is dead if no accepting state can be reached from it.
+    In a minimized DFA that is a rejecting state whose transitions all lead back to itself.
+    */
+    pub(crate) fn is_dead(&self, state: usize) -> bool {
+        self.marks[state] == 0 && self.transition[state].iter().all(|&next| next == state)
+    }
+
     /*
     This method is used to split the input string into tokens in an unamboguous way.
-    Read the comment above the lexical_scan function for more information.
+    Read the comment above the tokenize function for more information.
+    It reads bytes until the DFA can't accept anymore, then puts back the bytes after the longest accepted prefix
+    and returns that prefix with the mark of the state it ends in.
+    Each byte is read as the symbol with the same code, which is what DFAs in UTF-8 mode expect,
+    other DFAs only read ASCII input correctly.
     */
     pub fn get_longest_accepted(&self, istream: &mut VecDeque<u8>) -> (String, usize) {
+        self.get_longest_accepted_at(istream, false)
+    }
+
+    /*
+    Like get_longest_accepted, but also reads the virtual symbols of anchors, see regex::LINE_START:
+    LINE_START first if the input is at the start of a line, LINE_END before every newline and at the end of the input,
+    and TEXT_END after it. Virtual symbols don't add to the token, and one outside of the alphabet is skipped,
+    as the DFA has no anchors that need it. The DFA should come from an NFA built with Nfa::with_line_ends.
+    */
+    pub fn get_longest_accepted_at(&self, istream: &mut VecDeque<u8>, line_start: bool) -> (String, usize) {
         let mut current_state = 0;
-        let mut last_mark = 0;
-        let mut longest_accepted: Vec<u8> = vec![];
+        if line_start {
+            current_state = self.step(current_state, LINE_START).unwrap_or(current_state);
+        }
+        let mut longest_accepted = (0, 0); // (length, mark)
         let mut token_buffer: Vec<u8> = vec![];
-        
 
-        while !istream.is_empty() {
-            let next_token = istream.pop_front().unwrap();
-            current_state = self.transition[current_state][self.symbol_indices[&(next_token as char)]];
+        loop {
+            if istream.front().is_none_or(|&byte| byte == b'\n') {
+                current_state = self.step(current_state, LINE_END).unwrap_or(current_state);
+                if self.marks[current_state] > 0 && !token_buffer.is_empty() {
+                    longest_accepted = (token_buffer.len(), self.marks[current_state]);
+                }
+            }
+            let Some(next_token) = istream.pop_front() else {
+                if let Some(end_state) = self.step(current_state, TEXT_END).filter(|&state| self.marks[state] > 0) {
+                    if !token_buffer.is_empty() {
+                        longest_accepted = (token_buffer.len(), self.marks[end_state]);
+                    }
+                }
+                break
+            };
             token_buffer.push(next_token);
+            // a symbol outside of the alphabet can't be part of any token
+            let Some(next_state) = self.step(current_state, byte_symbol(next_token)) else {
+                break
+            };
+            current_state = next_state;
             if self.marks[current_state] > 0 {
-                longest_accepted.extend(token_buffer.iter());
-                last_mark = self.marks[current_state];
-                token_buffer.clear();
-            } else {
-                token_buffer.into_iter().rev().for_each(|x| istream.push_front(x));
+                longest_accepted = (token_buffer.len(), self.marks[current_state]);
+            } else if self.is_dead(current_state) {
                 break
             }
         }
 
-        (longest_accepted.into_iter().map(|x| x as char).collect::<String>(), last_mark)
+        let (length, last_mark) = longest_accepted;
+        token_buffer.drain(length..).rev().for_each(|x| istream.push_front(x));
+        (String::from_utf8_lossy(&token_buffer).into_owned(), last_mark)
+    }
 
+    // The state the DFA moves to from a state by reading a symbol, None if the symbol is not in the alphabet
+    pub fn step(&self, state: usize, symbol: char) -> Option<usize> {
+        self.symbol_indices.get(&symbol).map(|&index| self.transition[state][index])
     }
 
 }
@@ -219,7 +413,7 @@ impl DfaCloud {
         }).collect_vec()
     }
 
-    fn into_dfa_graph(&self) -> (Vec<Vec<usize>>, Vec<usize>) {
+    fn to_dfa_graph(&self) -> (Vec<Vec<usize>>, Vec<usize>) {
         let group0_index = self.state_groups[0];
         let mut translated: Vec<usize> = (0..self.state_groups.len()).collect();
         translated.swap(0, group0_index);
@@ -243,8 +437,9 @@ impl DfaCloud {
         let mut current_group_index = 0;
         while current_group_index < self.groups.len() {
             let current_group = &self.groups[current_group_index];
-            let subgroups: Vec<Vec<usize>> = current_group.into_iter()
-                                                            .map(|&x|{(&self.state_transition[x], x)})
+            // states stay together when each of their transitions leads to the same group
+            let subgroups: Vec<Vec<usize>> = current_group.iter()
+                                                            .map(|&x|{(self.state_transition[x].iter().map(|&next| self.state_groups[next]).collect_vec(), x)})
                                                             .into_group_map()
                                                             .into_values()
                                                             .sorted_by_key(|x| x[0])
@@ -261,8 +456,8 @@ impl DfaCloud {
 
             self.groups.remove(current_group_index);
 
-            for group in self.groups[current_group_index..].into_iter() {
-                group.into_iter().for_each(|x| self.state_groups[*x] += subgroup_num-1);
+            for group in self.groups[current_group_index..].iter() {
+                group.iter().for_each(|x| self.state_groups[*x] += subgroup_num-1);
             }
 
             for (i, subgroup) in subgroups.iter().enumerate() {
@@ -283,14 +478,14 @@ impl DfaCloud {
     }
 }
 
-impl ToString for Dfa {
-    fn to_string(&self) -> String {
+impl fmt::Display for Dfa {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut string = String::new();
         let sorted_alphabet = self.symbol_indices.keys().sorted_by_key(|x| self.symbol_indices[x]).collect_vec();
         string.push_str(format!("{:?}\n",sorted_alphabet).as_str());
         for i in 0..self.marks.len() {
             string.push_str(format!("{i} {:?} {:?}\n", self.transition[i], self.marks[i]).as_str())
         }
-        string
+        write!(f, "{string}")
     }
 }
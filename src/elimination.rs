@@ -0,0 +1,184 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use itertools::Itertools;
+
+use crate::{dfa::Dfa, nfa::Nfa, regex::{CharClass, Regex}};
+
+/*
+A generalized NFA, whose transitions are labeled with regexes instead of symbols, with at most one transition
+from a state to another. A state is eliminated by replacing every path p -> state -> q through it with a transition
+from p to q labeled R(p, state) R(state, state)* R(state, q), joined with the label p already had to q.
+Eliminating every state between a start and an end state leaves one transition whose label is the regex of the automaton.
+*/
+struct Gnfa {
+    // outgoing[p][q] is the label of the transition from p to q
+    outgoing: Vec<BTreeMap<usize, Regex>>,
+    // the states with a transition to each state
+    incoming: Vec<BTreeSet<usize>>,
+}
+
+impl Gnfa {
+    fn new(size: usize) -> Self {
+        Self { outgoing: vec![BTreeMap::new(); size], incoming: vec![BTreeSet::new(); size] }
+    }
+
+    fn add(&mut self, from: usize, to: usize, regex: Regex) {
+        let label = match self.outgoing[from].remove(&to) {
+            Some(label) => Regex::alt(vec![label, regex]),
+            None => regex,
+        };
+        self.outgoing[from].insert(to, label);
+        self.incoming[to].insert(from);
+    }
+
+    /*
+    An estimate of how much eliminating the state grows the labels, from Delgado and Morais:
+    the label of each incoming transition is copied once for every outgoing transition but one, and the other way around,
+    and the label of the loop once for every new transition but one.
+    Eliminating the states of the smallest weight first keeps the result small.
+    */
+    fn weight(&self, state: usize) -> usize {
+        let incoming = self.incoming[state].iter().filter(|&&from| from != state).map(|from| self.outgoing[*from][&state].size()).collect_vec();
+        let outgoing = self.outgoing[state].iter().filter(|(&to, _)| to != state).map(|(_, label)| label.size()).collect_vec();
+        let self_loop = self.outgoing[state].get(&state).map_or(0, Regex::size);
+
+        incoming.iter().sum::<usize>() * outgoing.len().saturating_sub(1)
+            + outgoing.iter().sum::<usize>() * incoming.len().saturating_sub(1)
+            + self_loop * (incoming.len() * outgoing.len()).saturating_sub(1)
+    }
+
+    fn eliminate(&mut self, state: usize) {
+        let outgoing = std::mem::take(&mut self.outgoing[state]);
+        let incoming = std::mem::take(&mut self.incoming[state]);
+        let self_loop = Regex::star(outgoing.get(&state).cloned().unwrap_or(Regex::Empty));
+
+        for &to in outgoing.keys() {
+            self.incoming[to].remove(&state);
+        }
+        for from in incoming.into_iter().filter(|&from| from != state) {
+            let into = self.outgoing[from].remove(&state).expect("every incoming state has a transition to the state");
+            for (&to, out) in outgoing.iter().filter(|(&to, _)| to != state) {
+                self.add(from, to, Regex::concat(vec![into.clone(), self_loop.clone(), out.clone()]));
+            }
+        }
+    }
+}
+
+impl Dfa {
+    /*
+    A regex for the strings the DFA gives the mark, found by state elimination.
+    Only the states on a path from the start to a state with the mark are kept, so dead states don't show up in the regex.
+    The states are eliminated in the order of Gnfa::weight, and every label is built with the simplifying
+    constructors of Regex, so the result is simplified, see Regex::simplified.
+    Transitions between the same two states are merged into one class.
+    For a DFA in UTF-8 mode the symbols are bytes, see utf8::byte_symbol, so the regex matches bytes as well.
+    */
+    pub fn to_regex(&self, mark: usize) -> Regex {
+        let size = self.marks.len();
+        let live = self.live_states(mark);
+        if !live[0] {
+            return Regex::Empty
+        }
+
+        // a new start and end state that are never eliminated
+        let (start, end) = (size, size + 1);
+        let alphabet = self.alphabet();
+        let mut gnfa = Gnfa::new(size + 2);
+        gnfa.add(start, 0, Regex::Epsilon);
+        for state in (0..size).filter(|&state| live[state]) {
+            let symbols_by_target = self.transition[state].iter().zip(&alphabet)
+                .filter(|(&next, _)| live[next])
+                .map(|(&next, &symbol)| (next, (symbol, symbol)))
+                .into_group_map();
+            for (next, ranges) in symbols_by_target {
+                gnfa.add(state, next, Regex::class(CharClass::new(false, ranges)));
+            }
+            if self.marks[state] == mark {
+                gnfa.add(state, end, Regex::Epsilon);
+            }
+        }
+
+        let mut remaining = (0..size).filter(|&state| live[state]).collect_vec();
+        while let Some(index) = remaining.iter().position_min_by_key(|&&state| gnfa.weight(state)) {
+            gnfa.eliminate(remaining.remove(index));
+        }
+        gnfa.outgoing[start].remove(&end).unwrap_or(Regex::Empty)
+    }
+
+    // Whether each state can be reached from the start and can reach a state with the mark
+    fn live_states(&self, mark: usize) -> Vec<bool> {
+        let size = self.marks.len();
+        let mut reachable = vec![false; size];
+        let mut stack = vec![0];
+        reachable[0] = true;
+        while let Some(state) = stack.pop() {
+            for &next in &self.transition[state] {
+                if !reachable[next] {
+                    reachable[next] = true;
+                    stack.push(next);
+                }
+            }
+        }
+
+        let mut previous = vec![vec![]; size];
+        for (state, row) in self.transition.iter().enumerate() {
+            for &next in row {
+                previous[next].push(state);
+            }
+        }
+        let mut live = self.marks.iter().zip(&reachable).map(|(&state_mark, &reachable)| reachable && state_mark == mark).collect_vec();
+        let mut stack = (0..size).filter(|&state| live[state]).collect_vec();
+        while let Some(state) = stack.pop() {
+            for &before in &previous[state] {
+                if reachable[before] && !live[before] {
+                    live[before] = true;
+                    stack.push(before);
+                }
+            }
+        }
+        live
+    }
+}
+
+impl Nfa {
+    /*
+    A regex for the strings the NFA gives the mark, see Dfa::to_regex.
+    The NFA is made deterministic first, so a string gets the largest mark of the states it can reach, like in Dfa::from_nfa.
+    */
+    pub fn to_regex(&self, mark: usize) -> Regex {
+        Dfa::from_nfa(self).to_regex(mark)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the strings of a rule set with each mark, read back from the regex printed for it
+    fn assert_round_trip(rules: &[&str], symbols: &str) {
+        let nfas = rules.iter().enumerate().map(|(i, rule)| Nfa::from_regex(rule, symbols, i + 1).unwrap()).collect_vec();
+        let dfa = Dfa::from_nfa(&Nfa::union(nfas.iter().collect()));
+
+        for mark in 0..=rules.len() {
+            let regex = dfa.to_regex(mark);
+            let class = Dfa::from_table(dfa.marks.iter().map(|&state_mark| usize::from(state_mark == mark)).collect(), dfa.transition.clone(), &dfa.alphabet());
+            let printed = Dfa::from_regex(&regex.to_string(), symbols, 1).unwrap();
+            assert!(printed.equivalent(&class), "{rules:?} mark {mark}: {regex}");
+        }
+    }
+
+    #[test]
+    fn to_regex_round_trips_every_mark() {
+        assert_round_trip(&["[a-z_][a-z0-9_]*", "if|var"], "abfirvz_09");
+        assert_round_trip(&["(a|b)*abb", "b+", "a(a|b)*&~(.*bb.*)"], "ab");
+        assert_round_trip(&["[0-9]+", "[0-9]+\\.[0-9]*", "\\.+"], "0123456789.");
+        assert_round_trip(&["a{2,4}", "c"], "abc");
+    }
+
+    #[test]
+    fn to_regex_of_a_missing_mark_is_empty() {
+        let dfa = Dfa::from_regex("ab*", "ab", 1).unwrap();
+        assert_eq!(dfa.to_regex(2), Regex::Empty);
+        assert_eq!(dfa.to_regex(1).to_string(), "ab*");
+    }
+}
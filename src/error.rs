@@ -0,0 +1,102 @@
+use std::fmt;
+
+/*
+The ways in which a regex can fail to compile into an automaton.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegexErrorKind {
+    EmptyRegex,
+    UnmatchedOpenParenthesis,
+    UnmatchedCloseParenthesis,
+    MissingOperand,
+    UnfinishedEscape,
+    InvalidEscape,
+    UnclosedClass,
+    InvalidRange(char, char),
+    UnclosedRepetition,
+    InvalidRepetition,
+    InvalidRepetitionBounds(usize, usize),
+    RepetitionTooLarge(usize),
+    RepetitionTooManyStates(usize),
+    SymbolNotInAlphabet(char),
+    InvalidDefinitionName(String),
+    UnclosedReference,
+    UndefinedName(String),
+    DefinitionCycle(String),
+    InvalidFlags,
+    UnsupportedOperator(char),
+    UnknownProperty(String),
+    UnknownCharacterClass(String),
+    ClassTooLargeToInfer(usize),
+    EmptyTokenBeforeContext,
+    InDefinition(String, usize, Box<RegexErrorKind>),
+}
+
+impl fmt::Display for RegexErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegexErrorKind::EmptyRegex => write!(f, "empty regex"),
+            RegexErrorKind::UnmatchedOpenParenthesis => write!(f, "couldn't find matching closing parenthesis"),
+            RegexErrorKind::UnmatchedCloseParenthesis => write!(f, "couldn't find matching opening parenthesis"),
+            RegexErrorKind::MissingOperand => write!(f, "operator is missing an operand"),
+            RegexErrorKind::UnfinishedEscape => write!(f, "regex ends with an unfinished escape"),
+            RegexErrorKind::InvalidEscape => write!(f, "invalid escape sequence"),
+            RegexErrorKind::UnclosedClass => write!(f, "couldn't find closing bracket of character class"),
+            RegexErrorKind::InvalidRange(start, end) => write!(f, "invalid range {start:?}-{end:?} in character class"),
+            RegexErrorKind::UnclosedRepetition => write!(f, "couldn't find closing brace of bounded repetition"),
+            RegexErrorKind::InvalidRepetition => write!(f, "bounded repetition must look like {{m}}, {{m,}} or {{m,n}}"),
+            RegexErrorKind::InvalidRepetitionBounds(min, max) => write!(f, "bounded repetition minimum {min} is larger than its maximum {max}"),
+            RegexErrorKind::RepetitionTooLarge(limit) => write!(f, "bounded repetition exceeds the limit of {limit}"),
+            RegexErrorKind::RepetitionTooManyStates(limit) => write!(f, "bounded repetition would expand to more than {limit} states"),
+            RegexErrorKind::SymbolNotInAlphabet(symbol) => write!(f, "symbol {symbol:?} is not in the alphabet"),
+            RegexErrorKind::InvalidDefinitionName(name) => write!(f, "{name:?} is not a valid definition name"),
+            RegexErrorKind::UnclosedReference => write!(f, "couldn't find closing brace of definition reference"),
+            RegexErrorKind::UndefinedName(name) => write!(f, "there is no definition named {name}"),
+            RegexErrorKind::DefinitionCycle(name) => write!(f, "definition {name} is used inside its own expansion"),
+            RegexErrorKind::InvalidFlags => write!(f, "inline flags must look like (?is:...) or (?i-s:...)"),
+            RegexErrorKind::UnsupportedOperator(operator) => write!(f, "operator {operator} is not supported by the Glushkov construction"),
+            RegexErrorKind::UnknownProperty(name) => write!(f, "there is no Unicode general category named {name}"),
+            RegexErrorKind::UnknownCharacterClass(name) => write!(f, "there is no POSIX character class named {name}"),
+            RegexErrorKind::ClassTooLargeToInfer(limit) => {
+                write!(f, "character class has more than {limit} symbols to infer, give the ones it should match as extra symbols or use UTF-8 mode")
+            },
+            RegexErrorKind::EmptyTokenBeforeContext => write!(f, "the token before the trailing context can be empty"),
+            RegexErrorKind::InDefinition(name, offset, kind) => write!(f, "{kind} at offset {offset} of definition {name}"),
+        }
+    }
+}
+
+/*
+An error found while compiling a regex.
+The offset is the byte offset in the pattern where the problem was found.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegexError {
+    pub kind: RegexErrorKind,
+    pub offset: usize,
+    pub pattern: String,
+}
+
+impl RegexError {
+    pub fn new(kind: RegexErrorKind, pattern: &str, offset: usize) -> Self {
+        RegexError { kind, offset, pattern: pattern.to_string() }
+    }
+
+    /*
+    Render the pattern with a caret under the offending position, for example
+        (ab|c
+        ^ couldn't find matching closing parenthesis
+    */
+    pub fn render(&self) -> String {
+        let column = self.pattern.get(..self.offset).map_or(0, |prefix| prefix.chars().count());
+        format!("{}\n{}^ {}", self.pattern, " ".repeat(column), self.kind)
+    }
+}
+
+impl fmt::Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset {} of regex {:?}", self.kind, self.offset, self.pattern)
+    }
+}
+
+impl std::error::Error for RegexError {}
@@ -0,0 +1,125 @@
+use itertools::Itertools;
+
+use crate::{nfa::{Label, Nfa}, regex::Regex};
+
+/*
+The positions of a regex are its symbol occurrences, a literal or a class each, numbered from 1 in the order they are written.
+For a sub-regex we need
+    nullable: whether it matches the empty string
+    first: the positions that can match the first symbol of a string it matches
+    last: the positions that can match the last symbol of a string it matches
+and for the whole regex the follow set of every position: the positions that can match the symbol right after it.
+*/
+struct Positions {
+    // the labels of the symbols each position matches
+    symbols: Vec<Vec<Label>>,
+    follow: Vec<Vec<usize>>,
+}
+
+struct Summary {
+    nullable: bool,
+    first: Vec<usize>,
+    last: Vec<usize>,
+}
+
+impl Positions {
+    fn add(&mut self, symbols: Vec<Label>) -> Summary {
+        self.symbols.push(symbols);
+        self.follow.push(vec![]);
+        let position = self.symbols.len();
+        Summary { nullable: false, first: vec![position], last: vec![position] }
+    }
+
+    fn connect(&mut self, from: &[usize], to: &[usize]) {
+        for &position in from {
+            self.follow[position - 1].extend(to);
+        }
+    }
+
+    fn summarize(&mut self, regex: &Regex) -> Summary {
+        match regex {
+            Regex::Empty => Summary { nullable: false, first: vec![], last: vec![] },
+            Regex::Epsilon => Summary { nullable: true, first: vec![], last: vec![] },
+            Regex::Literal(symbol) => self.add(vec![Label::symbol(*symbol)]),
+            Regex::Class(class) => self.add(Label::class(class)),
+            Regex::Concat(items) => {
+                let mut result = Summary { nullable: true, first: vec![], last: vec![] };
+                for item in items {
+                    let item = self.summarize(item);
+                    self.connect(&result.last, &item.first);
+                    if result.nullable {
+                        result.first.extend(&item.first);
+                    }
+                    result.last = if item.nullable { [result.last, item.last].concat() } else { item.last };
+                    result.nullable &= item.nullable;
+                }
+                result
+            },
+            Regex::Alt(items) => {
+                let mut result = Summary { nullable: false, first: vec![], last: vec![] };
+                for item in items {
+                    let item = self.summarize(item);
+                    result.nullable |= item.nullable;
+                    result.first.extend(item.first);
+                    result.last.extend(item.last);
+                }
+                result
+            },
+            Regex::Star(inner) | Regex::Plus(inner) => {
+                let inner_summary = self.summarize(inner);
+                self.connect(&inner_summary.last, &inner_summary.first);
+                Summary { nullable: inner_summary.nullable || matches!(regex, Regex::Star(_)), ..inner_summary }
+            },
+            Regex::Optional(inner) => Summary { nullable: true, ..self.summarize(inner) },
+            // a bounded repetition gets fresh positions for every copy, like in NfaFragment::repeat
+            Regex::Repeat { inner, min, max } => {
+                let copies = (0..*min).map(|_| (**inner).clone());
+                let rest = match max {
+                    Some(max) => vec![Regex::Optional(inner.clone()); max - min],
+                    None => vec![Regex::Star(inner.clone())],
+                };
+                self.summarize(&Regex::Concat(copies.chain(rest).collect()))
+            },
+            Regex::Intersect(_) | Regex::Complement(_) => {
+                panic!("the Glushkov construction can't build intersections and complements")
+            },
+        }
+    }
+}
+
+impl Nfa {
+    /*
+    Build the position automaton of a regex by Glushkov's construction.
+    State 0 is the start state and state p is entered after matching a symbol with position p,
+    so reading a symbol moves from the start state to the first positions matching it,
+    and from a position to the positions in its follow set that match it.
+    The accepting states are the last positions, and the start state if the regex matches the empty string.
+
+    The NFA has no epsilon-transitions, and the edges into a position are labeled with the symbols it matches.
+    The regex must not contain intersections or complements, Nfa::from_regex_with_options reports those as errors.
+    */
+    pub(crate) fn from_tree_glushkov(tree: &Regex, alphabet: Vec<char>, mark_num: usize) -> Nfa {
+        let mut positions = Positions { symbols: vec![], follow: vec![] };
+        let summary = positions.summarize(tree);
+        let num_states = positions.symbols.len() + 1;
+
+        let mut edges = vec![vec![]; num_states];
+        let successors = [summary.first.clone()].into_iter().chain(positions.follow.iter().cloned());
+        for (state, targets) in successors.enumerate() {
+            for target in targets.into_iter().sorted().dedup() {
+                edges[state].extend(positions.symbols[target - 1].iter().map(|&label| (label, target)));
+            }
+        }
+
+        let mut marks = vec![0; num_states];
+        for &position in &summary.last {
+            marks[position] = mark_num;
+        }
+        if summary.nullable {
+            marks[0] = mark_num;
+        }
+
+        let symbols_table = alphabet.into_iter().enumerate().map(|(i, symbol)| (symbol, i)).collect();
+        Nfa { marks, edges, symbols_table }
+    }
+}
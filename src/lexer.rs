@@ -0,0 +1,222 @@
+use std::{collections::VecDeque, ops::Range};
+
+use itertools::Itertools;
+
+use crate::{dfa::Dfa, error::{RegexError, RegexErrorKind}, nfa::{Nfa, RegexOptions}, regex::{parse, split_trailing_context, LINE_END, LINE_START}, utf8::byte_symbol};
+
+/*
+A part of the input, given both by its byte offsets and by its char offsets.
+The ranges start at the first byte or char of the part and end right after its last one.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub bytes: Range<usize>,
+    pub chars: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub class: String,
+    pub text: String,
+    pub span: Span,
+}
+
+struct Rule {
+    class: String,
+    // the DFAs of r and s for a rule r/s
+    trailing_context: Option<(Dfa, Dfa)>,
+}
+
+/*
+A lexer built from a list of rules, each a token class with the regex of its tokens.
+The rules are combined into one DFA that reads the bytes of UTF-8 text, see RegexOptions::utf8,
+and the input is split by the longest prefix method, see tokenize.
+When several rules match the longest prefix, the one listed last wins.
+
+A rule can be written r/s to only match r when it is followed by s, like in flex.
+The trailing context s is matched to choose the rule, but it isn't part of the token,
+and the scan goes on right after r. If the match can be split in several ways, r is taken as long as possible.
+The r of a rule r/s can't match the empty string.
+
+Rules can use the anchors ^ and $. The lexer keeps track of whether a token starts at the start of a line,
+and only then lets the DFA read the virtual symbol of ^, and it lets the DFA read the virtual symbol of $
+before every newline and at the end of the input, see Dfa::get_longest_accepted_at.
+Every rule is compiled as ^?(r), so rules without ^ match both at the start of a line and elsewhere.
+The anchors don't match any byte, so a rule ending with $ leaves the newline in the input.
+*/
+pub struct Lexer {
+    dfa: Dfa,
+    rules: Vec<Rule>,
+}
+
+impl Lexer {
+    pub fn new(rules: &[(&str, &str)], options: &RegexOptions) -> Result<Self, RegexError> {
+        let options = RegexOptions { utf8: true, ..options.clone() };
+
+        let mut machines = vec![];
+        let mut compiled_rules = vec![];
+        for (i, &(class, regex)) in rules.iter().enumerate() {
+            let trailing_context = match split_trailing_context(regex) {
+                None => {
+                    parse(regex, &options)?;
+                    machines.push(compile_anchored(regex, regex, i+1, &options)?);
+                    None
+                },
+                Some((head, tail)) => {
+                    parse(head, &options).map_err(|mut error| {
+                        error.pattern = regex.to_string();
+                        error
+                    })?;
+                    let head_dfa = Dfa::from_nfa(&compile_anchored(head, regex, 1, &options)?);
+                    // an empty token would leave the whole match in the input, so the scan couldn't go on
+                    if [String::new(), LINE_START.to_string()].into_iter().any(|start| head_dfa.accepts_before(start, true, true)) {
+                        return Err(RegexError::new(RegexErrorKind::EmptyTokenBeforeContext, regex, 0))
+                    }
+                    // report errors in s at their offset in the whole rule
+                    let tail_nfa = Nfa::from_regex_inferred_with_options(tail, "", 1, &options).map_err(|mut error| {
+                        error.offset += head.len() + 1;
+                        error.pattern = regex.to_string();
+                        error
+                    })?;
+                    let tail_dfa = Dfa::from_nfa(&tail_nfa.with_line_ends());
+                    machines.push(Nfa::from_regex_inferred_with_options(&format!("^?({head})({tail})"), "", i+1, &options)?.with_line_ends());
+                    Some((head_dfa, tail_dfa))
+                },
+            };
+            compiled_rules.push(Rule { class: class.to_string(), trailing_context });
+        }
+
+        let dfa = Dfa::from_nfa(&Nfa::union(machines.iter().collect_vec()));
+        Ok(Self { dfa, rules: compiled_rules })
+    }
+
+    /*
+    Split the input into tokens, leaving out the tokens of the class "ignored".
+    The scan stops at the first byte that doesn't start a token.
+    */
+    pub fn tokenize(&self, mut istream: VecDeque<u8>) -> Vec<Token> {
+        let mut tokens = vec![];
+
+        let (mut byte_offset, mut char_offset) = (0, 0);
+        let mut line_start = true;
+        while !istream.is_empty() {
+            // get the longest prefix that is accepted by any rule
+            let (mut text, mark) = self.dfa.get_longest_accepted_at(&mut istream, line_start);
+            if text.is_empty() {break}
+            let rule = &self.rules[mark-1];
+
+            // give the trailing context back to the input
+            if let Some((head, tail)) = &rule.trailing_context {
+                let bytes = text.as_bytes();
+                let start = if line_start { LINE_START.to_string() } else { String::new() };
+                let text_end = istream.is_empty();
+                let line_end = |i: usize| bytes.get(i).or(istream.front()).is_none_or(|&byte| byte == b'\n');
+                let split = (0..=bytes.len()).rev()
+                    .find(|&i| {
+                        head.accepts_before(start.clone() + &line_symbols(&bytes[..i]), line_end(i), false)
+                            && tail.accepts_before(line_symbols(&bytes[i..]), line_end(bytes.len()), text_end)
+                    })
+                    .expect("a match of r/s splits into a match of r followed by a match of s");
+                bytes[split..].iter().rev().for_each(|&x| istream.push_front(x));
+                text.truncate(split);
+            }
+            line_start = text.ends_with('\n');
+
+            let (byte_length, char_length) = (text.len(), text.chars().count());
+            let span = Span {
+                bytes: byte_offset..byte_offset + byte_length,
+                chars: char_offset..char_offset + char_length,
+            };
+            (byte_offset, char_offset) = (span.bytes.end, span.chars.end);
+
+            // Skip ignored tokens
+            if rule.class != "ignored" {
+                tokens.push(Token { class: rule.class.clone(), text, span });
+            }
+        }
+        tokens
+    }
+}
+
+/*
+Build the NFA of ^?(regex), which matches both at the start of a line and elsewhere, for a text where
+LINE_END is read at the end of every line, see Nfa::with_line_ends. The regex is already checked to parse,
+so the errors left are about its operators and are reported at their offset in the rule.
+*/
+pub(crate) fn compile_anchored(regex: &str, rule: &str, mark_num: usize, options: &RegexOptions) -> Result<Nfa, RegexError> {
+    let nfa = Nfa::from_regex_inferred_with_options(&format!("^?({regex})"), "", mark_num, options).map_err(|mut error| {
+        error.offset = error.offset.saturating_sub(3);
+        error.pattern = rule.to_string();
+        error
+    })?;
+    Ok(nfa.with_line_ends())
+}
+
+// The symbols the DFAs of the lexer read for the bytes, with LINE_END before every newline, see Dfa::get_longest_accepted_at
+fn line_symbols(bytes: &[u8]) -> String {
+    bytes.iter().flat_map(|&byte| (byte == b'\n').then_some(LINE_END).into_iter().chain([byte_symbol(byte)])).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(lexer: &Lexer, input: &str) -> Vec<(String, String)> {
+        lexer.tokenize(VecDeque::from(input.as_bytes().to_vec())).into_iter().map(|token| (token.class, token.text)).collect()
+    }
+
+    #[test]
+    fn empty_token_before_context_is_rejected() {
+        for rule in ["a*/b", "(a|\\e)/b", "^/b", "^?a?/b", "a*$/b"] {
+            let error = Lexer::new(&[("x", rule), ("y", "c")], &RegexOptions::default()).err().unwrap();
+            assert_eq!(error.kind, RegexErrorKind::EmptyTokenBeforeContext, "{rule}");
+        }
+    }
+
+    #[test]
+    fn trailing_context_stays_in_the_input() {
+        let lexer = Lexer::new(&[("x", "a+/b"), ("y", "[bc]")], &RegexOptions::default()).unwrap();
+        assert_eq!(texts(&lexer, "bcaab"), owned(&[("y", "b"), ("y", "c"), ("x", "aa"), ("y", "b")]));
+    }
+
+    fn lexed(rules: &[(&str, &str)], input: &str) -> Vec<(String, String)> {
+        texts(&Lexer::new(rules, &RegexOptions::default()).unwrap(), input)
+    }
+
+    fn owned(tokens: &[(&str, &str)]) -> Vec<(String, String)> {
+        tokens.iter().map(|&(class, text)| (class.to_string(), text.to_string())).collect()
+    }
+
+    #[test]
+    fn line_start_anchor_only_matches_at_the_start_of_a_line() {
+        let rules = [("comment", "^#[a-z]*"), ("hash", "#"), ("word", "[a-z]+"), ("ignored", "[ \n]")];
+        let expected = [("comment", "#x"), ("word", "a"), ("hash", "#"), ("word", "b"), ("comment", "#c"), ("hash", "#")];
+        assert_eq!(lexed(&rules, "#x a#b\n#c#"), owned(&expected));
+    }
+
+    #[test]
+    fn line_end_anchor_leaves_the_newline_in_the_input() {
+        let rules = [("x", "(a$)"), ("y", "b"), ("newline", "\n")];
+        assert_eq!(lexed(&rules, "a\nb"), owned(&[("x", "a"), ("newline", "\n"), ("y", "b")]));
+
+        let rules = [("x", "a$|b"), ("newline", "\n")];
+        assert_eq!(lexed(&rules, "a\nb"), owned(&[("x", "a"), ("newline", "\n"), ("x", "b")]));
+    }
+
+    #[test]
+    fn line_end_anchor_matches_before_a_newline_or_the_end_of_the_input() {
+        let rules = [("a", "a"), ("last", "a$"), ("b", "b"), ("ignored", "\n")];
+        let expected = [("last", "a"), ("a", "a"), ("b", "b"), ("last", "a")];
+        assert_eq!(lexed(&rules, "a\naba"), owned(&expected));
+
+        // the $ can be followed by the newline it stands before, and by \z at the end of the input
+        let rules = [("pair", "a$\nb"), ("end", r"b$\z"), ("a", "a"), ("b", "b")];
+        assert_eq!(lexed(&rules, "a\nbab"), owned(&[("pair", "a\nb"), ("a", "a"), ("end", "b")]));
+    }
+
+    #[test]
+    fn anchors_in_trailing_context() {
+        let rules = [("x", "a/$"), ("y", "a"), ("z", "b"), ("ignored", "\n")];
+        assert_eq!(lexed(&rules, "a\nab"), owned(&[("x", "a"), ("y", "a"), ("z", "b")]));
+    }
+}
@@ -1,30 +1,40 @@
-use std::{collections::{HashMap, VecDeque}, fmt::Error, rc::Rc};
-use dfa::Dfa;
-use itertools::Itertools;
-use nfa::Nfa;
+use std::collections::{HashMap, VecDeque};
+use error::RegexError;
+use lexer::{Lexer, Token};
+use nfa::{Construction, RegexOptions};
+use regex::Definitions;
 
 
 
+pub mod derivative;
 pub mod dfa;
+pub mod elimination;
+pub mod error;
+pub mod glushkov;
+pub mod lexer;
 pub mod nfa;
+pub mod regex;
+pub mod search;
+pub mod simplify;
+pub mod strings;
+mod unicode_tables;
+pub mod utf8;
 
 
-const DIGITS: &str = "0123456789";
-const LETTERS: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ_";
-
-/* 
-    Perform a lexical scan on given input file.
+/*
+    Split the given UTF-8 input into tokens.
     The method simulates an overarching DFA by coordinating smaller DFA's
     in a way that produces the same logic.
 
     The input is a buffer for the bytes of a source file.
     The lexical scan splits the input string into tokens by the method of longest prefix,
     then each token is categorized into one of the following classes, defined by the following regular expressions
-        keyword: var
-        identifier: L{L|D}* where L is the set of letters and D is the set of digits
+        keyword: var|print|if
+        identifier: L{L|D}* where L is the set of Unicode letters and '_', and D is the set of digits
           operator: {'+'|'-'|'*'|'/'|'='}
-           integer: DD* where D is the set of digits
-           ignored: {'\n', ''}
+           integer: D+ where D is the set of digits
+            string: '"' followed by any symbols other than '"' and newline, and a closing '"'
+           ignored: {';', whitespace}
 
     Note that there is ambiguity in how to split a string.
     For example, the string "55" could be split into 5 5, or it could be recognized as a single 55.
@@ -34,42 +44,46 @@ const LETTERS: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ_";
     This method takes the longest string from the start accepted by any DFA as the correct next token.
     Then, it removes that token and starts where it ends.
 
+    The DFA's read the bytes of the input, see RegexOptions::utf8, so any Unicode text is split correctly.
+    The scan stops at the first byte that doesn't start a token. Ignored tokens are left out of the result.
+    Rules can also have a trailing context that must follow the token, see Lexer.
     */
-pub fn lexical_scan(mut istream: VecDeque<u8>) -> Result<HashMap<String, String>, Error> {
-    let mut symbol_table = HashMap::<String,String>::new();
+pub fn tokenize(istream: VecDeque<u8>) -> Result<Vec<Token>, RegexError> {
+    // Named sub-patterns shared by the token regexes
+    let mut definitions = Definitions::new();
+    definitions.define("letter", r"[\p{L}_]")?;
+    definitions.define("digit", r"\d")?;
+    definitions.define("identifier", "{letter}({letter}|{digit})*")?;
+    let options = RegexOptions {
+        definitions,
+        simplify: true,
+        construction: Construction::Glushkov,
+        ..Default::default()
+    };
 
     // Set up DFA's and associate them with a class name
-    let letter_regex: String = LETTERS.chars().collect_vec().into_iter().interleave(vec!['|';LETTERS.len() - 1]).collect::<String>();
-    let digit_regex: String = DIGITS.chars().collect_vec().into_iter().interleave(vec!['|';DIGITS.len() - 1]).collect::<String>();
-
-    let identifier_regex = ["(",letter_regex.as_str(),")(",letter_regex.as_str(),"|",digit_regex.as_str(),")*"].concat();
-    let identifier_alphabet = [LETTERS, DIGITS].concat();
-
-
+    // The alphabet of each DFA is inferred from its regex
     let regexes = &[
-        ("identifier", identifier_regex.as_str(), identifier_alphabet.as_str()),
-        ("keyword", "(var|print|if)", "varpintf"),
-        ("operator", "(\\+|-|/|\\*|=)", "+-/*="),
-        ("integer", "(0|1|2|3|4|5|6|7|8|9)*", "0123456789"),
-        ("ignored", "(;| |\t|\r|\n)", "; \t\r\n"),
+        ("identifier", "{identifier}"),
+        ("keyword", "(var|print|if)"),
+        ("operator", "[-+/*=]"),
+        ("integer", "{digit}+"),
+        ("string", r#""[^"\n]*""#),
+        ("ignored", r"[;\s]"),
     ];
 
-    let machines: Vec<Nfa> = regexes.iter().enumerate().map(|(i, &(class,regex,alphabet))| {
-        Nfa::from_regex(regex, alphabet, i+1)
-    }).collect();
-
-    let lexer_nfa = Nfa::union(machines.iter().collect_vec());
-    let lexer = Dfa::from_nfa(&lexer_nfa);
+    let lexer = Lexer::new(regexes, &options)?;
+    Ok(lexer.tokenize(istream))
+}
 
-    while !istream.is_empty() {
-        // get the longest prefix that is accepted by any DFA
-        let (name, mark) = lexer.get_longest_accepted(&mut istream);
-        if name.is_empty() {break}
-        let class = regexes[mark-1].0;
-        // Skip ignored tokens
-        if !(class.contains("ignored") || symbol_table.contains_key(&name)){
-            symbol_table.insert(name, class.to_string());
-        }
+/*
+    Perform a lexical scan on given input file, see tokenize.
+    The result maps each token to its class, a token keeps the class of its first occurrence.
+    */
+pub fn lexical_scan(istream: VecDeque<u8>) -> Result<HashMap<String, String>, RegexError> {
+    let mut symbol_table = HashMap::<String,String>::new();
+    for token in tokenize(istream)? {
+        symbol_table.entry(token.text).or_insert(token.class);
     }
     Result::Ok(symbol_table)
-}
\ No newline at end of file
+}
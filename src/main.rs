@@ -1,6 +1,8 @@
-use std::{clone, collections::VecDeque, env::args, fs::File, io::Read};
+use std::{collections::VecDeque, env::args, fs::File, io::Read};
 
-use compiler::{dfa::Dfa, lexical_scan, nfa::Nfa};
+use itertools::Itertools;
+
+use compiler::{dfa::Dfa, lexical_scan, tokenize, nfa::{Nfa, RegexOptions}, search::Searcher, simplify::simplify};
 
 
 
@@ -11,9 +13,15 @@ fn main() {
 
     match args[1] {
         "lex" => test_lexical_scanner(args[2]),
-        "dfa" => test_dfa_from_regex(args[2], args[3]),
+        "tokens" => test_tokenize(args[2]),
+        "dfa" => test_dfa_from_regex(args[2], args.get(3).copied().unwrap_or("")),
         "nfa" => test_nfa_union(),
-        _ => println!("Incorrect argument. write 'lex <filename>' or 'dfa \"<regex>\" \"<alphabet>\"'")
+        "simplify" => test_simplify(args[2], args.get(3).copied().unwrap_or("")),
+        "check" => test_derivatives(args[2], args.get(3).copied().unwrap_or("")),
+        "strings" => test_strings(args[2], args[3], args.get(4).copied().unwrap_or("")),
+        "grep" => test_search(args[2], args[3]),
+        "regex" => test_to_regex(&args[2..]),
+        _ => println!("Incorrect argument. write 'lex <filename>', 'tokens <filename>', 'grep \"<regex>\" <filename>', 'regex \"<regex>\"...', 'strings <count> \"<regex>\" [\"<extra symbols>\"]' or one of 'dfa', 'simplify' or 'check' followed by \"<regex>\" [\"<extra symbols>\"]")
     }
 }
 
@@ -21,25 +29,31 @@ fn test_nfa_union() {
     let (regex1, alphabet1) = ("(a|b)*", "ab");
     let (regex2, alphabet2) = ("(cd)*", "cd");
 
-    let nfa1 = Nfa::from_regex(regex1, alphabet1, 1);
-    let nfa2 = Nfa::from_regex(regex2, alphabet2, 2);
+    let nfa1 = Nfa::from_regex(regex1, alphabet1, 1).unwrap();
+    let nfa2 = Nfa::from_regex(regex2, alphabet2, 2).unwrap();
 
     let nfa = Nfa::union(vec![&nfa1, &nfa2]);
     
     
-    print!("{}\n\n", nfa1.to_string());
-    print!("{}\n\n", nfa2.to_string());
-    print!("{}\n\n", nfa.to_string());
-    print!("{}\n\n",Dfa::from_nfa(&nfa1).to_string());
-    print!("{}\n\n",Dfa::from_nfa(&nfa2).to_string());
-    print!("{}\n\n",Dfa::from_nfa(&nfa).to_string());
+    print!("{}\n\n", nfa1);
+    print!("{}\n\n", nfa2);
+    print!("{}\n\n", nfa);
+    print!("{}\n\n",Dfa::from_nfa(&nfa1));
+    print!("{}\n\n",Dfa::from_nfa(&nfa2));
+    print!("{}\n\n",Dfa::from_nfa(&nfa));
+
+    // the same NFAs assembled from combinators instead of regexes
+    let built1 = Nfa::literal('a', 1).alt(&Nfa::literal('b', 1)).star();
+    let built2 = Nfa::literal('c', 2).concat(&Nfa::literal('d', 2)).star();
+    let built = Nfa::union(vec![&built1, &built2]);
+    println!("combinators give the same DFA: {}", Dfa::from_nfa(&built).equivalent(&Dfa::from_nfa(&nfa)));
 
 
 }
 
 fn test_lexical_scanner(arg:&str) {
 
-    let mut file = match File::open(arg.clone()) {
+    let mut file = match File::open(arg) {
         Ok(t) => t,
         Err(t) => panic!("{:?}", t)
     };
@@ -49,34 +63,147 @@ fn test_lexical_scanner(arg:&str) {
 
     let input = VecDeque::from(input);
 
-    println!("{:#?}", lexical_scan(input));
+    match lexical_scan(input) {
+        Ok(symbol_table) => println!("{:#?}", symbol_table),
+        Err(error) => println!("{}", error.render())
+    }
+}
+
+fn test_tokenize(arg:&str) {
+    let input = match std::fs::read(arg) {
+        Ok(t) => t,
+        Err(t) => panic!("{:?}", t)
+    };
+
+    match tokenize(VecDeque::from(input)) {
+        Ok(tokens) => for token in tokens {
+            println!("{:10} {:20} bytes {:?} chars {:?}", token.class, format!("{:?}", token.text), token.span.bytes, token.span.chars);
+        },
+        Err(error) => println!("{}", error.render())
+    }
+}
+
+fn test_simplify(regex:&str, extra_symbols:&str) {
+    match simplify(regex, extra_symbols, &RegexOptions::default()) {
+        Ok(result) => {
+            println!("original:   {} ({} NFA states)", result.original, result.states_before);
+            println!("simplified: {} ({} NFA states)", result.simplified, result.states_after);
+        },
+        Err(error) => println!("{}", error.render())
+    }
+}
+
+fn test_derivatives(regex:&str, extra_symbols:&str) {
+    let (nfa, from_derivatives) = match (
+        Nfa::from_regex_inferred(regex, extra_symbols, 1),
+        Dfa::from_regex_derivatives_inferred(regex, extra_symbols, 1)
+    ) {
+        (Ok(nfa), Ok(from_derivatives)) => (nfa, from_derivatives),
+        (Err(error), _) | (_, Err(error)) => return println!("{}", error.render())
+    };
+    let from_nfa = Dfa::from_nfa(&nfa);
+
+    println!("subset construction: {} states", from_nfa.marks.len());
+    println!("derivatives: {} states, {} after minimization", from_derivatives.marks.len(), from_derivatives.minimized().marks.len());
+    match from_nfa.distinguishing_string(&from_derivatives) {
+        None => println!("the DFAs are equivalent"),
+        Some(word) => println!("the DFAs disagree on {:?}", word)
+    }
+
+    let without_epsilons = nfa.remove_epsilons();
+    println!("NFA: {} states, {} after removing epsilon-transitions", nfa.marks.len(), without_epsilons.marks.len());
+    match from_nfa.distinguishing_string(&Dfa::from_nfa(&without_epsilons)) {
+        None => println!("removing epsilon-transitions keeps the language"),
+        Some(word) => println!("removing epsilon-transitions changes the language on {:?}", word)
+    }
+
+    // the NFA run by set simulation should agree with its DFA on strings it accepts and strings it rejects
+    let samples = from_nfa.strings(usize::MAX).take(100).chain(from_nfa.complement().strings(usize::MAX).take(100));
+    match samples.into_iter().find(|word| nfa.accepts(word) != from_nfa.accepts(word.clone())) {
+        None => println!("the NFA simulation agrees with the DFA"),
+        Some(word) => println!("the NFA simulation disagrees with the DFA on {:?}", word)
+    }
+}
+
+fn test_strings(count:&str, regex:&str, extra_symbols:&str) {
+    let Ok(count) = count.parse::<usize>() else {
+        return println!("Incorrect argument. the count of strings must be a number, not {:?}", count)
+    };
+    let dfa = match Dfa::from_regex_inferred(regex, extra_symbols, 1) {
+        Ok(dfa) => dfa,
+        Err(error) => return println!("{}", error.render())
+    };
+
+    for word in dfa.strings(usize::MAX).take(count) {
+        println!("{:?}", word);
+    }
+}
+
+fn test_search(regex:&str, arg:&str) {
+    let text = match std::fs::read_to_string(arg) {
+        Ok(t) => t,
+        Err(t) => panic!("{:?}", t)
+    };
+    let searcher = match Searcher::new(regex, &RegexOptions::default()) {
+        Ok(searcher) => searcher,
+        Err(error) => return println!("{}", error.render())
+    };
+
+    for range in searcher.find_iter(&text) {
+        println!("bytes {:?} {:?}", range, &text[range.clone()]);
+    }
+}
+
+/*
+Build the minimized DFA of a rule set, where a string gets the mark of the last rule that matches it like in a lexer,
+and print a regex for the strings of each mark, found by state elimination.
+Each regex is checked against the class it should describe: r & ~(s|t) for a rule r followed by the rules s and t.
+Every regex is read over the alphabets of all the rules together, so negated classes mean the same in each of them.
+*/
+fn test_to_regex(rules:&[&str]) {
+    let build = |symbols: &str| rules.iter().enumerate().map(|(i, rule)| Nfa::from_regex_inferred(rule, symbols, i + 1)).collect::<Result<Vec<_>, _>>();
+    let nfas = match build(&rules.concat()) {
+        Ok(nfas) => nfas,
+        Err(error) => return println!("{}", error.render())
+    };
+    let symbols: String = nfas.iter().flat_map(|nfa| nfa.alphabet()).map(|(symbol, _)| symbol).unique().collect();
+    let nfas = build(&symbols).expect("the rules were already parsed");
+    let dfa = Dfa::from_nfa(&Nfa::union(nfas.iter().collect()));
+    println!("minimized DFA: {} states", dfa.marks.len());
+
+    for (i, rule) in rules.iter().enumerate() {
+        let regex = dfa.to_regex(i + 1);
+        println!("{}: {}", rule, regex);
+
+        let later = &rules[i + 1..];
+        let class = if later.is_empty() { format!("({})", rule) } else { format!("({})&~({})", rule, later.join("|")) };
+        match (Dfa::from_regex_inferred(&regex.to_string(), &symbols, 1), Dfa::from_regex_inferred(&class, &symbols, 1)) {
+            (Ok(from_regex), Ok(from_class)) => match from_regex.distinguishing_string(&from_class) {
+                None => println!("    matches the same strings as {}", class),
+                Some(word) => println!("    disagrees with {} on {:?}", class, word)
+            },
+            (Err(error), _) | (_, Err(error)) => println!("{}", error.render())
+        }
+    }
 }
 
 fn test_dfa_from_regex(arg1:&str, arg2:&str) {
     let regex = arg1;
-    let alphabet = arg2;
-    println!("Making NFA from regex {:#?} with alphabet {:#?}", regex, alphabet);
-    let nfa = Nfa::from_regex(regex, alphabet, 2);
-    println!("{}",nfa.to_string());
+    let extra_symbols = arg2;
+    println!("Making NFA from regex {:#?} with extra symbols {:#?}", regex, extra_symbols);
+    let nfa = match Nfa::from_regex_inferred(regex, extra_symbols, 2) {
+        Ok(nfa) => nfa,
+        Err(error) => return println!("{}", error.render())
+    };
+    println!("{}",nfa);
     println!("\n\nMaking DFA from NFA");
     
     let dfa = Dfa::from_nfa(&nfa);
-    print!("{}",dfa.to_string());
+    print!("{}",dfa);
 
     
-    println!("\n\nEnter a word to run through the DFA or enter QUIT to exit");
-    loop {
-        let mut word = String::new();
-        let result = std::io::stdin().read_line(&mut word);
-        if let Ok(_) = result {
-            word.truncate(word.len() - 2);
-            
-            if word == "QUIT" {
-                break;
-            }
-            println!("{}", dfa.accepts(word.clone()));
-        } else {
-            println!("Error");
-        }
+    println!("\n\nShortest accepted words, up to 8 symbols long:");
+    for word in dfa.strings(8).take(20) {
+        println!("{:?}", word);
     }
 }
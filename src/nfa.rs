@@ -1,16 +1,115 @@
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::{collections::{HashMap, HashSet}, fmt, iter};
 
 use itertools::Itertools;
 
+use crate::{dfa::Dfa, error::{RegexError, RegexErrorKind}, regex::{ParsedRegex, parse_with_alphabet, parse_with_inferred_alphabet, CharClass, Definitions, Parser, Regex, LINE_END, TEXT_END}, utf8::byte_symbol};
+
+/*
+Options that change how a regex is read when building an NFA from it.
+    dot_matches_newline: the wildcard '.' also matches '\n'. By default it matches every other symbol of the alphabet.
+    case_insensitive: letters match both their upper and lower case forms,
+                      and the alphabet is extended with the other case of every such letter.
+    definitions: named sub-patterns the regex can use as {NAME}.
+    simplify: rewrite the regex with Regex::simplified before building the NFA, which usually gives fewer states.
+    construction: how the NFA is built from the regex, see Construction.
+    utf8: the NFA reads the bytes of UTF-8 encoded text instead of chars, see utf8::byte_symbol.
+          The regex is still written in chars, and its alphabet is made of the bytes of the chars of the given alphabet.
+    syntax: the syntax the regex is written in, see Syntax.
+The flags can also be turned on and off for part of a regex with groups like (?i:select) or (?-s:...).
+*/
+#[derive(Debug, Clone, Default)]
+pub struct RegexOptions {
+    pub dot_matches_newline: bool,
+    pub case_insensitive: bool,
+    pub definitions: Definitions,
+    pub simplify: bool,
+    pub construction: Construction,
+    pub utf8: bool,
+    pub syntax: Syntax,
+}
+
+/*
+The ways of building an NFA from a regex.
+    Thompson: one small fragment per operator glued together with epsilon-transitions, see NfaFragment.
+    Glushkov: one state per symbol occurrence in the regex plus a start state, without epsilon-transitions.
+              Determinizing it doesn't have to follow epsilon-transitions, but it can't handle the '&' and '~' operators.
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Construction {
+    #[default]
+    Thompson,
+    Glushkov,
+}
+
+/*
+The syntaxes a regex can be written in.
+    Native: the syntax described at regex::Parser, with definitions, inline flags, escapes such as \d and \p{..},
+            and the '&' and '~' operators.
+    PosixExtended: POSIX extended regular expressions as used by grep -E, so patterns from other tools can be used unchanged.
+                   A '\' takes the next symbol literally, '{' always starts a bounded repetition,
+                   '&' and '~' are ordinary symbols, and bracket expressions can use classes such as [[:alpha:]].
+                   See the CASES of the tests in regex for a table of patterns and the strings they match.
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Syntax {
+    #[default]
+    Native,
+    PosixExtended,
+}
+
+/*
+The label of an NFA edge: an epsilon-transition, which reads no symbol,
+or the range of symbols from start to end, both included, which matches the symbols of the alphabet in it.
+A single symbol is the range from that symbol to itself.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Label {
+    Epsilon,
+    Range(char, char),
+}
+
+impl Label {
+    pub fn symbol(symbol: char) -> Self {
+        Label::Range(symbol, symbol)
+    }
+
+    // The labels matching the symbols of a class, one range each
+    pub fn class(class: &CharClass) -> Vec<Self> {
+        class.positive_ranges().into_iter().map(|(start, end)| Label::Range(start, end)).collect()
+    }
+
+    pub fn matches(&self, symbol: char) -> bool {
+        match *self {
+            Label::Epsilon => false,
+            Label::Range(start, end) => (start..=end).contains(&symbol),
+        }
+    }
+}
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Label::Epsilon => write!(f, "ε"),
+            Label::Range(start, end) if start == end => write!(f, "{start:?}"),
+            Label::Range(start, end) => write!(f, "{start:?}-{end:?}"),
+        }
+    }
+}
+
+/*
+An NFA stored as lists of edges: edges[state] has the label and the target state of every edge leaving the state.
+A state with no edge for a symbol has no transition on it, so large alphabets and classes cost nothing
+for the states that don't read them. The alphabet gives the symbols the NFA reads, and the columns of the DFA built from it.
+*/
 #[derive(Debug, Clone)]
 pub struct Nfa {
     pub marks: Vec<usize>,
-    pub transition: Vec<Vec<Option<Vec<usize>>>>, // 2d Array where elements are sets of states,
+    pub edges: Vec<Vec<(Label, usize)>>,
     pub symbols_table: HashMap<char, usize>,
 }
 
 impl Nfa {
-    fn new(transition: Vec<Vec<Option<Vec<usize>>>>, marks: Vec<usize>, alphabet: Vec<char>) -> Self {
+    fn new(edges: Vec<Vec<(Label, usize)>>, marks: Vec<usize>, alphabet: Vec<char>) -> Self {
         let symbols_table: HashMap<char, usize> = HashMap::from_iter(
             alphabet.iter().enumerate().map(|(i, &symbol)|{
                 (symbol, i)
@@ -18,47 +117,86 @@ impl Nfa {
         );
 
 
-        Nfa { marks, transition, symbols_table }
+        Nfa { marks, edges, symbols_table }
     }
 
-    pub fn from_regex(regex: &str, alphabet: &str, mark_num:usize) -> Nfa {
-        let alphabet: Vec<char> = alphabet.chars().collect();
-        let fragment = NfaFragment::from_regex(regex, &alphabet);
+    pub fn from_regex(regex: &str, alphabet: &str, mark_num:usize) -> Result<Nfa, RegexError> {
+        Self::from_regex_with_options(regex, alphabet, mark_num, &RegexOptions::default())
+    }
 
-        let mut transition = fragment.transition;
+    pub fn from_regex_with_options(regex: &str, alphabet: &str, mark_num:usize, options: &RegexOptions) -> Result<Nfa, RegexError> {
+        let parsed = parse_with_alphabet(regex, alphabet, options)?;
+        Self::build(regex, parsed, mark_num, options)
+    }
 
-        
-        // Add new state 
-        transition.push(vec![None; transition[0].len()]);
+    /*
+    Build an NFA whose alphabet is inferred from the symbols written in the regex, see Regex::symbols.
+    The extra symbols are added to the inferred alphabet,
+    this is needed when a negated class or a wildcard should match symbols the regex doesn't mention.
+    */
+    pub fn from_regex_inferred(regex: &str, extra_symbols: &str, mark_num:usize) -> Result<Nfa, RegexError> {
+        Self::from_regex_inferred_with_options(regex, extra_symbols, mark_num, &RegexOptions::default())
+    }
 
+    pub fn from_regex_inferred_with_options(regex: &str, extra_symbols: &str, mark_num:usize, options: &RegexOptions) -> Result<Nfa, RegexError> {
+        let parsed = parse_with_inferred_alphabet(regex, extra_symbols, options)?;
+        Self::build(regex, parsed, mark_num, options)
+    }
 
-        // Add transition from out-state to new final state with out-state symbol
-        let (out_state, out_symbol) = fragment.out;
-        let num_states = transition.len();
-        let out_symbol = out_symbol.unwrap_or(alphabet.len());
+    fn build(regex: &str, parsed: ParsedRegex, mark_num:usize, options: &RegexOptions) -> Result<Nfa, RegexError> {
+        let ParsedRegex { tree, alphabet, boolean_operators } = parsed;
+        let tree = if options.simplify { tree.simplified() } else { tree };
 
-        let out_range = &mut transition[out_state][out_symbol];
-        if let Some(states) = out_range {
-            states.push(num_states - 1)
-        } else {
-            *out_range = Some(vec![num_states - 1])
+        match options.construction {
+            Construction::Thompson => Ok(Self::from_tree(&tree, alphabet, mark_num)),
+            Construction::Glushkov => match boolean_operators.first() {
+                Some(&(operator, offset)) => Err(RegexError::new(RegexErrorKind::UnsupportedOperator(operator), regex, offset)),
+                None => Ok(Self::from_tree_glushkov(&tree, alphabet, mark_num)),
+            },
         }
+    }
+
+    pub(crate) fn from_tree(tree: &Regex, alphabet: Vec<char>, mark_num:usize) -> Nfa {
+        let fragment = NfaFragment::from_regex(tree, &alphabet);
+
+        let mut edges = fragment.edges;
+
+        
+        // Add new state 
+        edges.push(vec![]);
+
+
+        // Add transition from out-state to new final state with out-state labels
+        let num_states = edges.len();
+        NfaFragment::attach(&mut edges, &fragment.out, num_states - 1);
 
         // Make the new final state the only accepting state
         let mut marks = vec![0; num_states];
         *marks.last_mut().unwrap() = mark_num;
 
-        Self::new(transition, marks, alphabet)
+        Self::new(edges, marks, alphabet)
+    }
+
+    // The symbols of the alphabet sorted, each with its column in the DFA built from the NFA
+    pub fn alphabet(&self) -> Vec<(char, usize)> {
+        self.symbols_table.iter().map(|(&symbol, &column)| (symbol, column)).sorted().collect()
+    }
+
+    // NFAs from the Glushkov construction and from remove_epsilons have no epsilon-transitions
+    pub fn has_epsilons(&self) -> bool {
+        self.edges.iter().flatten().any(|&(label, _)| label == Label::Epsilon)
     }
 
     pub fn empty_closure(&self, states: Vec<usize>) -> Option<Vec<usize>> {
-        let epsilon_index = self.transition[0].len() - 1;
         let mut target_stack = states;
 
         target_stack.sort();
         target_stack.dedup();
+        if target_stack.is_empty() {
+            return None
+        }
 
-        let mut closure = vec![false; self.transition.len()];
+        let mut closure = vec![false; self.edges.len()];
         
 
 
@@ -67,8 +205,8 @@ impl Nfa {
             
             if !closure[candidate] {
                 // add states in epsilon transition range of candidate state
-                self.transition[candidate][epsilon_index].clone().unwrap_or(vec![]).iter().for_each(|&x |{
-                    if !closure[x] {
+                self.edges[candidate].iter().for_each(|&(label, x)|{
+                    if label == Label::Epsilon && !closure[x] {
                         target_stack.push(x)
                     }
                 });
@@ -90,254 +228,481 @@ impl Nfa {
         Some(result)
     }
 
-    pub fn union (nfas:Vec<&Nfa>) -> Self {
-        let mut symbols = HashSet::<char>::new();
-        let mut marks = vec![0];
-        for nfa in nfas.iter() {
-            symbols.extend(nfa.symbols_table.keys());
-            marks.extend(nfa.marks.clone());
+    /*
+    Run the NFA on a string by keeping the set of states it can be in, without building a DFA,
+    so the time grows linearly with the length of the string even for regexes whose DFA is exponentially large.
+    Returns the mark of the string, the largest mark of the states it ends in like in Dfa::from_nfa, or 0 if it isn't accepted.
+    */
+    pub fn accepted_mark(&self, symbols: impl IntoIterator<Item = char>) -> usize {
+        let mut states = self.empty_closure(vec![0]).unwrap_or_default();
+        for symbol in symbols {
+            states = self.step(&states, symbol);
         }
-        let symbols_num = symbols.len();
-        let symbols_table: HashMap<char, usize> = symbols.clone().into_iter().enumerate().map(|(x,y)| (y,x)).collect();
+        self.mark(&states)
+    }
 
-        let mut transition = vec![vec![None; symbols_num + 1]];
-        let mut new_start_epsilon_transitions:Vec<usize> = vec![];
+    pub fn accepts(&self, word: &str) -> bool {
+        self.accepted_mark(word.chars()) > 0
+    }
 
+    // Run an NFA in UTF-8 mode, see RegexOptions::utf8, on a string of bytes
+    pub fn accepts_bytes(&self, word: &[u8]) -> bool {
+        self.accepted_mark(word.iter().map(|&byte| byte_symbol(byte))) > 0
+    }
 
+    /*
+    The longest prefix of the symbols that the NFA accepts, given by its number of symbols, with its mark.
+    The symbols are read by set simulation like in accepted_mark until no state is left.
+    Returns None if no prefix, not even the empty one, is accepted.
+    */
+    pub fn longest_match(&self, symbols: impl IntoIterator<Item = char>) -> Option<(usize, usize)> {
+        let mut states = self.empty_closure(vec![0]).unwrap_or_default();
+        let mut longest = Some((0, self.mark(&states))).filter(|&(_, mark)| mark > 0);
+        for (i, symbol) in symbols.into_iter().enumerate() {
+            states = self.step(&states, symbol);
+            if states.is_empty() {
+                break
+            }
+            let mark = self.mark(&states);
+            if mark > 0 {
+                longest = Some((i + 1, mark));
+            }
+        }
+        longest
+    }
 
-        let mut nfa_offset = 1;
-        for nfa in nfas.iter() {
-            new_start_epsilon_transitions.push(nfa_offset);
-            let num_nfa_states = nfa.marks.len();
-            let key_indices: HashMap<usize, char> = nfa.symbols_table.clone().into_iter().map(|(x,y)| (y,x)).collect();
-            let num_nfa_symbols = key_indices.len();
-
-
-            for state_index in 0..num_nfa_states {
-                let translated_transitions = nfa.transition[state_index].iter().map(|out_states| {
-                    if let Some(states) = out_states {
-                        Some(states.into_iter().map(|x| nfa_offset + x).collect_vec())
-                    } else {
-                        None
-                    }
-                }).collect_vec();
-                
-                let mut new_transitions: Vec<Option<Vec<usize>>> = vec![None; symbols_num+1];
-                
-                for symbol_index in 0..num_nfa_symbols {
-                    let translated_symbol_index = symbols_table[&key_indices[&symbol_index]];
-                    new_transitions[translated_symbol_index] = translated_transitions[symbol_index].clone();
-                }
+    // The states reached from a set of states by reading a symbol, with their empty closure
+    fn step(&self, states: &[usize], symbol: char) -> Vec<usize> {
+        if !self.symbols_table.contains_key(&symbol) {
+            return vec![]
+        }
+        let targets = states.iter()
+            .flat_map(|&state| &self.edges[state])
+            .filter(|(label, _)| label.matches(symbol))
+            .map(|&(_, target)| target)
+            .collect();
+        self.empty_closure(targets).unwrap_or_default()
+    }
 
-                *new_transitions.last_mut().unwrap() = translated_transitions.last().unwrap().clone();
+    fn mark(&self, states: &[usize]) -> usize {
+        states.iter().map(|&state| self.marks[state]).max().unwrap_or_default()
+    }
 
-                transition.push(new_transitions);
-            }
+    fn max_mark(&self) -> usize {
+        self.marks.iter().copied().max().unwrap_or_default()
+    }
 
-            nfa_offset += num_nfa_states
+    /*
+    The NFA for a text where LINE_END is read at the end of every line, see regex::LINE_END.
+    Every state that reads a newline or TEXT_END gets a loop on LINE_END, so the paths that don't match a $
+    there read it without moving, and only the paths that do need it can stop right after it.
+    */
+    pub(crate) fn with_line_ends(&self) -> Nfa {
+        let mut nfa = self.clone();
+        let column = nfa.symbols_table.len();
+        nfa.symbols_table.entry(LINE_END).or_insert(column);
+        for (state, state_edges) in nfa.edges.iter_mut().enumerate() {
+            if state_edges.iter().any(|(label, _)| label.matches('\n') || label.matches(TEXT_END)) {
+                state_edges.push((Label::symbol(LINE_END), state));
+            }
         }
+        nfa
+    }
 
-        *transition[0].last_mut().unwrap() = Some(new_start_epsilon_transitions);
+    /*
+    An NFA accepting the reverses of the strings this NFA accepts, with every edge turned around.
+    A new start state has epsilon-transitions to the accepting states, and the old start state is the only accepting one,
+    with the largest mark of this NFA, so the marks of different strings aren't told apart anymore.
+    */
+    pub fn reverse(&self) -> Nfa {
+        let num_states = self.marks.len() + 1;
+        let mut edges = vec![vec![]; num_states];
+        for (state, state_edges) in self.edges.iter().enumerate() {
+            for &(label, target) in state_edges {
+                edges[target + 1].push((label, state + 1));
+            }
+        }
+        edges[0] = (0..self.marks.len()).filter(|&state| self.marks[state] > 0).map(|state| (Label::Epsilon, state + 1)).collect();
 
-        Nfa { marks, transition, symbols_table }
+        let mut marks = vec![0; num_states];
+        marks[1] = self.max_mark();
+        Nfa { marks, edges, symbols_table: self.symbols_table.clone() }
     }
-}
 
-#[derive(Debug)]
-struct NfaFragment {
-    transition: Vec<Vec<Option<Vec<usize>>>>,
-    out:(usize, Option<usize>) // (state, out_symbol)
-}
+    /*
+    An equivalent NFA without epsilon-transitions.
+    A state gets the edges of every state in its empty closure, and the largest mark among them,
+    so a set of states of the new NFA has the same mark as its empty closure in this one, like in Dfa::from_nfa.
+    States that can no longer be reached from the start state are removed, and the others keep their order.
+    */
+    pub fn remove_epsilons(&self) -> Nfa {
+        let mut edges = vec![];
+        let mut marks = vec![];
+        for state in 0..self.edges.len() {
+            let closure = self.empty_closure(vec![state]).unwrap_or_default();
+            edges.push(closure.iter()
+                .flat_map(|&state| &self.edges[state])
+                .filter(|(label, _)| *label != Label::Epsilon)
+                .copied()
+                .sorted()
+                .dedup()
+                .collect_vec());
+            marks.push(self.mark(&closure));
+        }
 
-impl NfaFragment {
-const OPERATORS: &'static str = ")(|+*"; // in order of lower to higher precedence
-
-    fn prec(operator:char) -> usize{
-        NfaFragment::OPERATORS.find(operator).unwrap()
-    }
-
-    fn regex_to_postfix(regex: &str) -> Vec<char> {
-        // Add concatenation + operators in between consecutive terms (tokens and parenthesized or starred expressions)
-        let mut previous_was_term = false;
-        let mut escape = false;
-        let mut token_list: Vec<char> = Vec::new();
-        let mut raw_stack: VecDeque<char> = regex.chars().collect();
-        while !raw_stack.is_empty() {
-            let token = raw_stack.pop_front().unwrap();
-            let token_is_operator = Self::OPERATORS.contains(token); 
-
-            if !escape {
-                if token == '\\' {escape = true};
-                if previous_was_term && (token == '(' || !token_is_operator) {
-                    token_list.push('+');
-                }
-                if ")*".contains(token) || !token_is_operator {
-                    previous_was_term = true
-                } else {
-                    previous_was_term = false
+        // keep the states reachable from the start state
+        let mut reachable = vec![false; edges.len()];
+        let mut stack = vec![0];
+        reachable[0] = true;
+        while let Some(state) = stack.pop() {
+            for &(_, next) in &edges[state] {
+                if !reachable[next] {
+                    reachable[next] = true;
+                    stack.push(next);
                 }
             }
-            
-            token_list.push(token);
-        }
-        let mut postfix_list: Vec<char> = vec![];
-        let mut op_stack: Vec<char> = vec![];
-        let mut escape = false;
-
-        for token in token_list {
-            if escape {
-                postfix_list.push(token);
-                escape = false;
-                continue
-            } else if token == '\\' {
-                escape = true;
-            }
-            if Self::OPERATORS.contains(token) {
-                let precedence = Self::prec(token);
-                match token {
-                    '(' => op_stack.push(token),
-                    ')' => {
-                        if let Some( mut top_token) = op_stack.pop() {
-                            while top_token != '(' {
-                                postfix_list.push(top_token);
-                                if let Some(op) = op_stack.pop() {
-                                    top_token = op;
-                                } else {
-                                    panic!("Couldn't find matching parenthesis")
-                                }
-                            }
-                        } else {
-                            panic!("Couldn't find operation in the stack")
-                        }
-                    },
-                    _ => {
-                        while   !op_stack.is_empty() &&
-                                Self::prec(*op_stack.last().unwrap()) >= precedence {
-                                    postfix_list.push(op_stack.pop().unwrap())
-                        }
-                        op_stack.push(token)
-                    }
-                }
-            } else {
-                postfix_list.push(token)
+        }
+        let new_index = reachable.iter().scan(0, |count, &is_reachable| {
+            *count += is_reachable as usize;
+            Some(*count - 1)
+        }).collect_vec();
+
+        let (edges, marks) = edges.into_iter().zip(marks).zip(&reachable)
+            .filter(|(_, &is_reachable)| is_reachable)
+            .map(|((state_edges, mark), _)| {
+                (state_edges.into_iter().map(|(label, target)| (label, new_index[target])).collect(), mark)
+            })
+            .unzip();
+        Nfa { marks, edges, symbols_table: self.symbols_table.clone() }
+    }
+
+    /*
+    Combine NFAs into one that accepts the strings of each of them with the mark they give it,
+    with a new start state that has an epsilon-transition to the start of every NFA.
+    */
+    pub fn union (nfas:Vec<&Nfa>) -> Self {
+        let (mut nfa, starts) = Self::placed(&nfas, 1);
+        nfa.edges[0] = starts.into_iter().map(|start| (Label::Epsilon, start)).collect();
+        nfa
+    }
+
+    /*
+    The combinators below build NFAs without going through a regex. Like union they keep the marks of their operands,
+    and a new accepting state for the empty string gets the largest mark of the NFA it is added to.
+    */
+
+    // The NFA accepting only the empty string, over an empty alphabet
+    pub fn epsilon(mark_num: usize) -> Nfa {
+        Self::new(vec![vec![]], vec![mark_num], vec![])
+    }
+
+    // The NFA accepting only the symbol, over the alphabet made of that symbol
+    pub fn literal(symbol: char, mark_num: usize) -> Nfa {
+        Self::new(vec![vec![(Label::symbol(symbol), 1)], vec![]], vec![0, mark_num], vec![symbol])
+    }
+
+    // The NFA accepting any symbol of the class that is in the alphabet
+    pub fn class(class: &CharClass, alphabet: &[char], mark_num: usize) -> Nfa {
+        let edges = Label::class(class).into_iter().map(|label| (label, 1)).collect();
+        Self::new(vec![edges, vec![]], vec![0, mark_num], alphabet.to_vec())
+    }
+
+    /*
+    The NFA of a string accepted by this NFA followed by a string accepted by the other.
+    The accepting states of this NFA get an epsilon-transition to the start of the other one and lose their marks,
+    so the strings get their marks from the other NFA.
+    */
+    pub fn concat(&self, other: &Nfa) -> Nfa {
+        let (mut nfa, starts) = Self::placed(&[self, other], 0);
+        for state in 0..self.marks.len() {
+            if nfa.marks[state] > 0 {
+                nfa.marks[state] = 0;
+                nfa.edges[state].push((Label::Epsilon, starts[1]));
             }
         }
+        nfa
+    }
 
+    pub fn alt(&self, other: &Nfa) -> Nfa {
+        Self::union(vec![self, other])
+    }
+
+    // Zero or more strings accepted by this NFA in a row
+    pub fn star(&self) -> Nfa {
+        self.plus().optional()
+    }
 
-        while !op_stack.is_empty() {
-            postfix_list.push(op_stack.pop().unwrap())
+    // One or more strings accepted by this NFA in a row, every accepting state gets an epsilon-transition back to the start
+    pub fn plus(&self) -> Nfa {
+        let mut nfa = self.clone();
+        for state in 0..nfa.marks.len() {
+            if nfa.marks[state] > 0 {
+                nfa.edges[state].push((Label::Epsilon, 0));
+            }
         }
+        nfa
+    }
 
-        postfix_list
+    // Accept the empty string too, with a new start state that has epsilon-transitions to the old start and to a new accepting state
+    pub fn optional(&self) -> Nfa {
+        let (mut nfa, starts) = Self::placed(&[self], 1);
+        nfa.edges.push(vec![]);
+        nfa.marks.push(self.max_mark());
+        nfa.edges[0] = vec![(Label::Epsilon, starts[0]), (Label::Epsilon, nfa.marks.len() - 1)];
+        nfa
     }
 
-    fn from_regex(regex: &str, alphabet: &Vec<char>) -> Self {
-        let postfix = Self::regex_to_postfix(regex);
-        let mut eval_stack: Vec<Self> = vec![];
-        let mut escape = false;
+    /*
+    Between min and max strings accepted by this NFA in a row, with no upper bound if max is None.
+    Like a bounded repetition in a regex, it is expanded into copies of the NFA, chained like in concat,
+    and it has the same limits: the counts can't be larger than Parser::MAX_REPETITION,
+    and the copies can't have more than Parser::MAX_REPETITION_SIZE states in total.
+    The errors aren't about a regex, so their pattern is empty and their offset is 0:
+    only their kind means something, render and Display don't.
+    */
+    pub fn repeat(&self, min: usize, max: Option<usize>) -> Result<Nfa, RegexError> {
+        let error = |kind| Err(RegexError::new(kind, "", 0));
+        match max {
+            Some(max) if min > max => return error(RegexErrorKind::InvalidRepetitionBounds(min, max)),
+            _ if min.max(max.unwrap_or(0)) > Parser::MAX_REPETITION => {
+                return error(RegexErrorKind::RepetitionTooLarge(Parser::MAX_REPETITION))
+            },
+            _ => (),
+        }
+        let copies = max.unwrap_or(min + 1);
+        if self.marks.len().saturating_mul(copies) > Parser::MAX_REPETITION_SIZE {
+            return error(RegexErrorKind::RepetitionTooManyStates(Parser::MAX_REPETITION_SIZE))
+        }
+        if copies == 0 {
+            return Ok(Self::epsilon(self.max_mark()))
+        }
 
-        for token in postfix {
-            if escape {
-                match token {
-                    'e' => eval_stack.push(Self::epsilon(alphabet)),
-                    _ => eval_stack.push(Self::symbol(token, alphabet))
+        let rest = match max {
+            Some(_) => self.optional(),
+            None => self.star(),
+        };
+        let parts = iter::repeat_n(self, min).chain(iter::repeat_n(&rest, copies - min)).collect_vec();
+        let (mut nfa, starts) = Self::placed(&parts, 0);
+        // the accepting states of each copy lead to the start of the next one
+        for i in 1..parts.len() {
+            for state in starts[i - 1]..starts[i] {
+                if nfa.marks[state] > 0 {
+                    nfa.marks[state] = 0;
+                    nfa.edges[state].push((Label::Epsilon, starts[i]));
                 }
-                escape = false;
-                continue
-            } else if token == '\\' {
-                escape = true;
-                continue
             }
+        }
+        Ok(nfa)
+    }
 
-            if !Self::OPERATORS.contains(token) {
-                eval_stack.push(Self::symbol(token, &alphabet));
-            } else {
-                match token {
-                    '*' => {
-                        let fragment = eval_stack.pop().unwrap();
-                        eval_stack.push(Self::star(fragment));
-                    }, 
-                    '+' => {
-                        let frag2 = eval_stack.pop().unwrap(); 
-                        let frag1 = eval_stack.pop().unwrap();
-                        eval_stack.push(Self::concatenate(frag1, frag2));
-                    },
-                    '|' => {
-                        let frag2 = eval_stack.pop().unwrap(); 
-                        let frag1 = eval_stack.pop().unwrap();
-                        eval_stack.push(Self::union(frag1, frag2))
-                    
-                    },
-                    _ => panic!()
-                }
+    /*
+    Copy the states of NFAs one after the other into a new NFA, after `offset` states left without edges for the caller.
+    The alphabet is the union of their alphabets, and each label is split so that it still only matches
+    the symbols of the alphabet of its own NFA, like a negated class that was built for that alphabet.
+    Returns the new NFA with the state each of the NFAs starts at.
+    */
+    fn placed(nfas: &[&Nfa], offset: usize) -> (Nfa, Vec<usize>) {
+        let mut symbols = HashSet::<char>::new();
+        let mut marks = vec![0; offset];
+        for nfa in nfas.iter() {
+            symbols.extend(nfa.symbols_table.keys());
+            marks.extend(nfa.marks.clone());
+        }
+        let symbols_table: HashMap<char, usize> = symbols.clone().into_iter().enumerate().map(|(x,y)| (y,x)).collect();
+        let all_symbols = symbols.into_iter().sorted().collect_vec();
+
+        let mut edges = vec![vec![]; offset];
+        let mut starts = vec![];
+        let mut nfa_offset = offset;
+        for nfa in nfas.iter() {
+            starts.push(nfa_offset);
+            let own_symbols = nfa.alphabet().into_iter().map(|(symbol, _)| symbol).collect_vec();
+            let same_alphabet = own_symbols.len() == all_symbols.len();
+
+            for state_edges in &nfa.edges {
+                edges.push(state_edges.iter().flat_map(|&(label, target)| {
+                    let labels = if same_alphabet { vec![label] } else { Self::restrict(label, &own_symbols, &all_symbols) };
+                    labels.into_iter().map(move |label| (label, nfa_offset + target))
+                }).collect());
             }
+
+            nfa_offset += nfa.marks.len()
         }
 
-        eval_stack.pop().unwrap()
+        (Nfa { marks, edges, symbols_table }, starts)
+    }
+
+    /*
+    Split a label into the ranges matching the same symbols of a larger alphabet as the label matches of a smaller one.
+    Both alphabets must be sorted.
+    */
+    fn restrict(label: Label, own_symbols: &[char], all_symbols: &[char]) -> Vec<Label> {
+        let Label::Range(start, end) = label else { return vec![label] };
+        let owned = |symbol: &char| own_symbols.binary_search(symbol).is_ok();
+        let (first, last) = (all_symbols.partition_point(|&symbol| symbol < start), all_symbols.partition_point(|&symbol| symbol <= end));
+        all_symbols[first..last]
+            .chunk_by(|a, b| owned(a) == owned(b))
+            .filter(|run| owned(&run[0]))
+            .map(|run| Label::Range(run[0], run[run.len() - 1]))
+            .collect()
     }
+}
 
-    fn symbol(symbol:char, alphabet: &Vec<char>) -> Self {
-        let transition = vec![vec![None; alphabet.len() + 1]];
-        if let Some(index) = alphabet.iter().position(|x| *x==symbol) {
-            Self {transition, out:(0, Some(index))}
-        } else {
-            println!("calling symbol method");
-            println!("symbol {symbol}");
-            println!("alphabet{:?}", alphabet);
-            panic!()
+/*
+The edges of the states of a DFA, leaving out the transitions into states that can't reach an accepting state.
+The symbols leading to the same state that are next to each other in the sorted alphabet share an edge.
+*/
+fn dfa_edges(dfa: &Dfa) -> Vec<Vec<(Label, usize)>> {
+    let alphabet = dfa.alphabet();
+    let columns = (0..alphabet.len()).sorted_by_key(|&i| alphabet[i]).collect_vec();
+
+    // find the states from which an accepting state can be reached
+    let mut live = dfa.marks.iter().map(|&mark| mark > 0).collect_vec();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for state in 0..dfa.marks.len() {
+            if !live[state] && dfa.transition[state].iter().any(|&next| live[next]) {
+                live[state] = true;
+                changed = true;
+            }
         }
     }
 
-    fn epsilon(alphabet: &Vec<char>) -> Self {
-        let transition = vec![vec![None; alphabet.len() + 1]];
-        Self {transition, out:(0, None)}
+    dfa.transition.iter().map(|row| {
+        columns.chunk_by(|&a, &b| row[a] == row[b])
+            .filter(|run| live[row[run[0]]])
+            .map(|run| (Label::Range(alphabet[run[0]], alphabet[run[run.len() - 1]]), row[run[0]]))
+            .collect_vec()
+    }).collect()
+}
+
+// The NFA with the states, marks and alphabet of a DFA
+impl From<&Dfa> for Nfa {
+    fn from(dfa: &Dfa) -> Self {
+        Nfa::new(dfa_edges(dfa), dfa.marks.clone(), dfa.alphabet())
+    }
+}
+
+impl From<Dfa> for Nfa {
+    fn from(dfa: Dfa) -> Self {
+        Nfa::from(&dfa)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct NfaFragment {
+    edges: Vec<Vec<(Label, usize)>>,
+    out:(usize, Vec<Label>) // (state, out_labels), [Label::Epsilon] is an epsilon out-transition
+}
+
+impl NfaFragment {
+    /*
+    Build a fragment from a regex by Thompson's construction.
+    Literals outside of the alphabet match nothing, callers check for them beforehand to report an error.
+    */
+    fn from_regex(regex: &Regex, alphabet: &[char]) -> Self {
+        match regex {
+            Regex::Empty => Self::class(&CharClass::new(false, vec![])),
+            Regex::Epsilon => Self::epsilon(),
+            Regex::Literal(symbol) => Self::symbol(*symbol),
+            Regex::Class(class) => Self::class(class),
+            Regex::Concat(items) => items.iter()
+                .map(|item| Self::from_regex(item, alphabet))
+                .reduce(Self::concatenate)
+                .unwrap_or_else(Self::epsilon),
+            Regex::Alt(items) => items.iter()
+                .map(|item| Self::from_regex(item, alphabet))
+                .reduce(Self::union)
+                .unwrap_or_else(|| Self::class(&CharClass::new(false, vec![]))),
+            Regex::Star(inner) => Self::star(Self::from_regex(inner, alphabet)),
+            Regex::Plus(inner) => Self::plus(Self::from_regex(inner, alphabet)),
+            Regex::Optional(inner) => Self::optional(Self::from_regex(inner, alphabet)),
+            Regex::Repeat { inner, min, max } => Self::repeat(Self::from_regex(inner, alphabet), *min, *max),
+            Regex::Intersect(parts) => {
+                let dfa = parts.iter()
+                    .map(|part| Dfa::from_nfa(&Nfa::from_tree(part, alphabet.to_vec(), 1)))
+                    .reduce(|a, b| a.intersection(&b))
+                    .unwrap_or_else(|| Dfa::from_nfa(&Nfa::from_tree(&Regex::Empty, alphabet.to_vec(), 1)).complement());
+                Self::from_dfa(&dfa)
+            },
+            Regex::Complement(inner) => {
+                let dfa = Dfa::from_nfa(&Nfa::from_tree(inner, alphabet.to_vec(), 1)).complement();
+                Self::from_dfa(&dfa)
+            },
+        }
+    }
+
+    /*
+    Intersection and complement have no Thompson construction,
+    so their operands are turned into DFAs which are combined and copied back into a fragment.
+    The DFA must have been built over the same alphabet as the fragment.
+    Its states become the states of the fragment, see dfa_edges,
+    and its accepting states get an epsilon-transition to a new out-state.
+    */
+    fn from_dfa(dfa: &Dfa) -> Self {
+        let num_states = dfa.marks.len();
+        let mut edges = dfa_edges(dfa);
+        edges.push(vec![]);
+
+        for (state, &mark) in dfa.marks.iter().enumerate() {
+            if mark > 0 {
+                edges[state].push((Label::Epsilon, num_states));
+            }
+        }
+
+        Self {edges, out:(num_states, vec![Label::Epsilon])}
+    }
+
+    fn symbol(symbol:char) -> Self {
+        Self {edges: vec![vec![]], out:(0, vec![Label::symbol(symbol)])}
+    }
+
+    /*
+    A character class is a single state whose out-transition is labeled with the ranges of the class,
+    so [a-z] costs one state and one edge instead of the chain of unions (a|b|...|z) would.
+    */
+    fn class(class: &CharClass) -> Self {
+        Self {edges: vec![vec![]], out:(0, Label::class(class))}
+    }
+
+    fn epsilon() -> Self {
+        Self {edges: vec![vec![]], out:(0, vec![Label::Epsilon])}
+    }
+
+    // Add edges from the out-state of a fragment to the target state, labeled with the out-labels.
+    fn attach(edges: &mut [Vec<(Label, usize)>], out: &(usize, Vec<Label>), target: usize) {
+        let (out_state, out_labels) = out;
+        edges[*out_state].extend(out_labels.iter().map(|&label| (label, target)));
     }
 
     fn shifted(self, shift:usize) -> Self {
-        let transition = self.transition.iter().map(|state| {
-            state.iter().map(|symbol| {
-                match symbol {
-                    None => None,
-                    Some(states) => Some(
-                        states.iter().map(|&x| {x + shift}).collect::<Vec<usize>>()
-                    )
-                }
-            }).collect::<Vec<Option<Vec<usize>>>>()
-        }).collect::<Vec<Vec<Option<Vec<usize>>>>>();
+        let edges = self.edges.into_iter().map(|state_edges| {
+            state_edges.into_iter().map(|(label, target)| (label, target + shift)).collect_vec()
+        }).collect_vec();
 
         let out = (self.out.0 + shift, self.out.1);
 
-        Self{transition, out}
+        Self{edges, out}
     }
 
     fn concatenate(a:Self, b:Self) -> Self {
-        let a_num_states = a.transition.len();
-        let extended_alphabet_size = a.transition[0].len();
-        
+        let a_num_states = a.edges.len();
+
         let b = b.shifted(a_num_states);
 
-        let mut transition = [a.transition, b.transition].concat();
-        
+        let mut edges = [a.edges, b.edges].concat();
+
         // insert transition from out-state of a to in-state of b
         // NOTE: b in-state index has been shifted from 0 to a_len
-        let out_state_index = a.out.0;
-        // default position is the last column corresponding to epsilon-transitions
-        let out_symbol_index = a.out.1.unwrap_or(extended_alphabet_size-1);
-        let a_out = &mut transition[out_state_index][out_symbol_index];
-        
-        match a_out {
-            Some(states) => states.push(a_num_states),
-            None => *a_out = Some(vec![a_num_states])
-        }
+        Self::attach(&mut edges, &a.out, a_num_states);
 
-        Self {transition, out:b.out}
+        Self {edges, out:b.out}
     }
 
     fn union(a: Self, b:Self) -> Self {
-        let a_num_states = a.transition.len();
-        let epsilon_index = a.transition[0].len() - 1;
-        let alphabet_size = epsilon_index;
-        
+        let a_num_states = a.edges.len();
+
 
         // shift state indices in a and b
         // new lawout is 0, A, B, 1
@@ -345,78 +710,249 @@ const OPERATORS: &'static str = ")(|+*"; // in order of lower to higher preceden
         //       A is the set of states of a
         //       B is the set of states of b
         //       1 is the new out-state
-        let Self{transition:a_transition, out:a_out} = a.shifted(1);
-        let Self{transition:b_transition, out:b_out} = b.shifted(1 + a_num_states);
+        let Self{edges:a_edges, out:a_out} = a.shifted(1);
+        let Self{edges:b_edges, out:b_out} = b.shifted(1 + a_num_states);
 
 
-        let mut transition = [
-            vec![vec![None; alphabet_size + 1]],
-            a_transition,
-            b_transition,
-            vec![vec![None; alphabet_size + 1]]
+        let mut edges = [
+            vec![vec![]],
+            a_edges,
+            b_edges,
+            vec![vec![]]
         ].concat();
 
         // set epsilon-transition from new state to start of a and b
-        transition[0][epsilon_index] = Some(vec![1, 1 + a_num_states]);
-        // set transitions from out-states of a and b to start of new out-state with respective out-symbols of a and b
-        let new_out_state_index = transition.len()-1;
+        edges[0] = vec![(Label::Epsilon, 1), (Label::Epsilon, 1 + a_num_states)];
+        // set transitions from out-states of a and b to start of new out-state with respective out-labels of a and b
+        let new_out_state_index = edges.len()-1;
         for old_out in [a_out, b_out] {
-            let old_out_range = &mut transition[old_out.0][old_out.1.unwrap_or(epsilon_index)];
-            match old_out_range {
-                Some(states) => states.push(new_out_state_index),
-                None => *old_out_range = Some(vec![new_out_state_index])
-            }
+            Self::attach(&mut edges, &old_out, new_out_state_index);
         }
 
-        let out = (new_out_state_index, None);
+        let out = (new_out_state_index, vec![Label::Epsilon]);
 
-        Self{transition, out}
+        Self{edges, out}
     }
 
     fn star(old: Self) -> Self {
-        let extended_alphabet_size = old.transition[0].len();
         // shift state indices to make space for new state
         let old = old.shifted(1);
 
         // add new state at the start
-        let mut transition = [
-            vec![vec![None; extended_alphabet_size]],
-            old.transition
+        let mut edges = [
+            vec![vec![]],
+            old.edges
         ].concat();
 
         // set epsilon-transition from new start state to old start state
-        let epsilon_index = extended_alphabet_size - 1;
-        transition[0][epsilon_index] = Some(vec![1]);
+        edges[0].push((Label::Epsilon, 1));
 
         // set transition from old out-state to new out-state
-        // use out symbol
-        let out_symbol = if let Some(symbol) = old.out.1 {
-            symbol
-        } else {
-            epsilon_index
-        };
-        
-        if let Some(out_range) = &mut transition[old.out.0][out_symbol] {
-            out_range.push(0)
-        } else {
-            transition[old.out.0][out_symbol] = Some(vec![0])
-        }
+        // use out labels
+        Self::attach(&mut edges, &old.out, 0);
+
+        let out = (0, vec![Label::Epsilon]);
+
+        Self{edges, out}
+    }
+
+    fn plus(old: Self) -> Self {
+        let mut edges = old.edges;
+
+        // add new out-state at the end
+        edges.push(vec![]);
+        let new_out_state_index = edges.len() - 1;
+        Self::attach(&mut edges, &old.out, new_out_state_index);
+
+        // set epsilon-transition from the new out-state back to the start, so the fragment can repeat
+        edges[new_out_state_index].push((Label::Epsilon, 0));
+
+        let out = (new_out_state_index, vec![Label::Epsilon]);
+
+        Self{edges, out}
+    }
+
+    fn optional(old: Self) -> Self {
+        // shift state indices to make space for new start state
+        let old = old.shifted(1);
 
-        let out = (0, None);
+        // layout is 0, O, 1
+        // where 0 is the new in-state, O is the set of old states and 1 is the new out-state
+        let mut edges = [
+            vec![vec![]],
+            old.edges,
+            vec![vec![]]
+        ].concat();
+        let new_out_state_index = edges.len() - 1;
+
+        // set epsilon-transitions from new start state to old start state and to new out-state
+        edges[0] = vec![(Label::Epsilon, 1), (Label::Epsilon, new_out_state_index)];
+        Self::attach(&mut edges, &old.out, new_out_state_index);
 
-        Self{transition, out}
+        let out = (new_out_state_index, vec![Label::Epsilon]);
+
+        Self{edges, out}
+    }
+
+    /*
+    Expand a bounded repetition into copies of the fragment:
+    {m,n} is m copies followed by n-m optional copies and {m,} is m copies followed by a starred copy.
+    */
+    fn repeat(old: Self, min: usize, max: Option<usize>) -> Self {
+        let mut result = Self::epsilon();
+
+        for _ in 0..min {
+            result = Self::concatenate(result, old.clone());
+        }
+        match max {
+            Some(max) => for _ in min..max {
+                result = Self::concatenate(result, Self::optional(old.clone()));
+            },
+            None => result = Self::concatenate(result, Self::star(old))
+        }
+
+        result
     }
 }
 
-impl ToString for Nfa {
-    fn to_string(&self) -> String {
+impl fmt::Display for Nfa {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut string = String::new();
         let sorted_alphabet = self.symbols_table.keys().sorted_by_key(|x| self.symbols_table[x]).collect_vec();
         string.push_str(format!("{:?}\n",sorted_alphabet).as_str());
         for i in 0..self.marks.len() {
-            string.push_str(format!("{i} {:?} {:?}\n", self.transition[i], self.marks[i]).as_str())
+            let edges = self.edges[i].iter().map(|(label, target)| format!("{label} -> {target}")).join(", ");
+            string.push_str(format!("{i} [{edges}] {:?}\n", self.marks[i]).as_str())
         }
 
-        string
+        write!(f, "{string}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // rule sets, each rule with the mark of its position, so strings matched by several rules get the largest mark
+    const RULE_SETS: &[&[&str]] = &[
+        &["(a|b)*abb"],
+        &["a*b?", "ab+", "(ab)*"],
+        &["[ab]+", "ba", "b*a*b"],
+        &["(a|b)*a(a|b)(a|b)(a|b)"],
+        &["a{2,3}|b", "~(.*bb.*)&(a|b)+", "c"],
+    ];
+
+    fn rules_nfa(rules: &[&str]) -> Nfa {
+        let nfas = rules.iter().enumerate().map(|(i, rule)| Nfa::from_regex(rule, "abc", i + 1).unwrap()).collect_vec();
+        Nfa::union(nfas.iter().collect())
     }
-}
\ No newline at end of file
+
+    // every string over the symbols up to the given length
+    fn words(symbols: &str, max_len: usize) -> Vec<Vec<char>> {
+        (0..=max_len).flat_map(|len| (0..len).map(|_| symbols.chars()).multi_cartesian_product()).collect()
+    }
+
+    // the marks a DFA gives to the prefixes of a word, stopping at its dead state
+    fn dfa_marks(dfa: &Dfa, word: &[char]) -> Vec<usize> {
+        let mut state = 0;
+        let mut marks = vec![dfa.marks[state]];
+        for &symbol in word {
+            state = dfa.step(state, symbol).unwrap();
+            if dfa.is_dead(state) {
+                break
+            }
+            marks.push(dfa.marks[state]);
+        }
+        marks
+    }
+
+    #[test]
+    fn set_simulation_agrees_with_subset_construction() {
+        for rules in RULE_SETS {
+            let nfa = rules_nfa(rules);
+            let dfa = Dfa::from_nfa(&nfa);
+            for word in words("abc", 6) {
+                let marks = dfa_marks(&dfa, &word);
+                let text: String = word.iter().collect();
+                let mark = if marks.len() == word.len() + 1 { marks[word.len()] } else { 0 };
+                assert_eq!(nfa.accepted_mark(word.iter().copied()), mark, "{rules:?} on {text:?}");
+                assert_eq!(nfa.accepts(&text), mark > 0, "{rules:?} on {text:?}");
+
+                let longest = marks.iter().enumerate().rev().find(|&(_, &mark)| mark > 0).map(|(len, &mark)| (len, mark));
+                assert_eq!(nfa.longest_match(word.iter().copied()), longest, "{rules:?} on {text:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn repeat_checks_its_bounds() {
+        let a = Nfa::literal('a', 1);
+        let error = a.repeat(3, Some(2)).unwrap_err();
+        assert_eq!(error.kind, RegexErrorKind::InvalidRepetitionBounds(3, 2));
+        let error = a.repeat(0, Some(1_000_000)).unwrap_err();
+        assert_eq!(error.kind, RegexErrorKind::RepetitionTooLarge(Parser::MAX_REPETITION));
+        let error = a.repeat(5000, None).unwrap_err();
+        assert_eq!(error.kind, RegexErrorKind::RepetitionTooLarge(Parser::MAX_REPETITION));
+        let wide = Nfa::from_regex("[ab]{200}", "ab", 1).unwrap();
+        assert!(wide.marks.len() * 1000 > Parser::MAX_REPETITION_SIZE);
+        let error = wide.repeat(1000, Some(1000)).unwrap_err();
+        assert_eq!(error.kind, RegexErrorKind::RepetitionTooManyStates(Parser::MAX_REPETITION_SIZE));
+
+        // the largest repetition allowed is built in one pass, not by copying the whole NFA for every count
+        let repeated = a.repeat(0, Some(Parser::MAX_REPETITION)).unwrap();
+        assert!(repeated.accepts(&"a".repeat(1000)) && !repeated.accepts(&"a".repeat(1001)));
+
+        for (min, max, pattern) in [(0, Some(0), r"\e"), (2, Some(4), "a{2,4}"), (2, None, "a{2,}"), (0, Some(1), "a?"), (0, None, "a*"), (3, Some(3), "aaa")] {
+            let built = Dfa::from_nfa(&a.repeat(min, max).unwrap());
+            assert!(built.equivalent(&Dfa::from_regex(pattern, "a", 1).unwrap()), "{pattern}");
+        }
+    }
+
+    #[test]
+    fn remove_epsilons_keeps_marks_and_trims_unreachable_states() {
+        for rules in RULE_SETS {
+            let mut nfa = rules_nfa(rules);
+            // a state no edge leads to, with an edge back into the NFA
+            nfa.marks.push(rules.len() + 1);
+            nfa.edges.push(vec![(Label::symbol('a'), 0)]);
+
+            let without_epsilons = nfa.remove_epsilons();
+            assert!(!without_epsilons.has_epsilons(), "{rules:?}");
+            assert!(without_epsilons.marks.len() < nfa.marks.len(), "{rules:?}");
+
+            let mut reachable = vec![false; without_epsilons.marks.len()];
+            let mut stack = vec![0];
+            reachable[0] = true;
+            while let Some(state) = stack.pop() {
+                for &(_, target) in &without_epsilons.edges[state] {
+                    if !reachable[target] {
+                        reachable[target] = true;
+                        stack.push(target);
+                    }
+                }
+            }
+            assert!(reachable.iter().all(|&reachable| reachable), "{rules:?}");
+
+            for word in words("abc", 6) {
+                let text: String = word.iter().collect();
+                assert_eq!(without_epsilons.accepted_mark(word.iter().copied()), nfa.accepted_mark(word.iter().copied()), "{rules:?} on {text:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn set_simulation_avoids_the_subset_blow_up() {
+        // the DFA of (a|b)*a(a|b){n} needs 2^(n+1) states to remember the last n+1 symbols
+        let n = 24;
+        let nfa = Nfa::from_regex(&format!("(a|b)*a(a|b){{{n}}}"), "ab", 1).unwrap();
+        assert!(nfa.marks.len() < 200);
+
+        let text: String = (0..200).map(|i| if i * 7 % 5 < 2 { 'a' } else { 'b' }).collect();
+        let accepted = |end: usize| end > n && text.as_bytes()[end - n - 1] == b'a';
+        for end in 0..=text.len() {
+            assert_eq!(nfa.accepts(&text[..end]), accepted(end), "{end}");
+        }
+        let longest = (0..=text.len()).rev().find(|&end| accepted(end)).map(|end| (end, 1));
+        assert_eq!(nfa.longest_match(text.chars()), longest);
+    }
+}
@@ -0,0 +1,1170 @@
+use std::{collections::HashMap, fmt, iter};
+
+use itertools::Itertools;
+
+use crate::{error::{RegexError, RegexErrorKind}, nfa::{RegexOptions, Syntax}, unicode_tables::GENERAL_CATEGORIES, utf8};
+
+/*
+A regular expression as a syntax tree.
+    Empty: matches no string at all
+    Epsilon: matches only the empty string
+    Literal: matches a single symbol
+    Class: matches any single symbol of a character class
+    Concat: matches the concatenation of its parts, in order
+    Alt: matches any of its alternatives
+    Star, Plus, Optional: match zero or more, one or more, and zero or one repetitions of the inner regex
+    Repeat: matches between min and max repetitions of the inner regex, with no upper bound if max is None
+    Intersect: matches the strings matched by all of its parts
+    Complement: matches the strings over the alphabet that the inner regex doesn't match
+*/
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Regex {
+    Empty,
+    Epsilon,
+    Literal(char),
+    Class(CharClass),
+    Concat(Vec<Regex>),
+    Alt(Vec<Regex>),
+    Star(Box<Regex>),
+    Plus(Box<Regex>),
+    Optional(Box<Regex>),
+    Repeat { inner: Box<Regex>, min: usize, max: Option<usize> },
+    Intersect(Vec<Regex>),
+    Complement(Box<Regex>),
+}
+
+/*
+A set of symbols given by a list of inclusive ranges.
+A negated class contains every symbol of the alphabet that is not in any of the ranges.
+*/
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CharClass {
+    pub negated: bool,
+    pub ranges: Vec<(char, char)>,
+}
+
+impl CharClass {
+    // Build a class with its ranges sorted and overlapping or adjacent ranges merged
+    pub fn new(negated: bool, mut ranges: Vec<(char, char)>) -> Self {
+        ranges.sort();
+        let mut merged: Vec<(char, char)> = vec![];
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some((_, last_end)) if (*last_end as u32).saturating_add(1) >= start as u32 => {
+                    *last_end = (*last_end).max(end)
+                },
+                _ => merged.push((start, end))
+            }
+        }
+        CharClass { negated, ranges: merged }
+    }
+
+    /*
+    The shorthand class for \d, \w or \s, or the negation of one of them for \D, \W and \S.
+    */
+    pub fn shorthand(name: char) -> Self {
+        let ranges = match name.to_ascii_lowercase() {
+            'd' => vec![('0', '9')],
+            'w' => vec![('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')],
+            's' => vec![('\t', '\r'), (' ', ' ')],
+            _ => panic!("{name:?} is not the name of a shorthand class"),
+        };
+        CharClass::new(name.is_ascii_uppercase(), ranges)
+    }
+
+    /*
+    The class of a Unicode general category, given by its two letter name like Lu,
+    or by its first letter like L for the union of the categories starting with it.
+    */
+    pub fn general_category(name: &str, negated: bool) -> Option<Self> {
+        let ranges = GENERAL_CATEGORIES.iter()
+            .filter(|(category, _)| *category == name || name.len() == 1 && category.starts_with(name))
+            .flat_map(|(_, ranges)| ranges.iter().copied())
+            .collect_vec();
+        (!ranges.is_empty()).then(|| CharClass::new(negated, ranges))
+    }
+
+    /*
+    The class of a POSIX character class such as alpha in [[:alpha:]], with the members it has in the C locale.
+    */
+    pub fn posix(name: &str) -> Option<Self> {
+        let ranges = match name {
+            "alpha" => vec![('A', 'Z'), ('a', 'z')],
+            "digit" => vec![('0', '9')],
+            "alnum" => vec![('0', '9'), ('A', 'Z'), ('a', 'z')],
+            "upper" => vec![('A', 'Z')],
+            "lower" => vec![('a', 'z')],
+            "space" => vec![('\t', '\r'), (' ', ' ')],
+            "blank" => vec![('\t', '\t'), (' ', ' ')],
+            "punct" => vec![('!', '/'), (':', '@'), ('[', '`'), ('{', '~')],
+            "print" => vec![(' ', '~')],
+            "graph" => vec![('!', '~')],
+            "cntrl" => vec![('\0', '\x1f'), ('\x7f', '\x7f')],
+            "xdigit" => vec![('0', '9'), ('A', 'F'), ('a', 'f')],
+            _ => return None,
+        };
+        Some(CharClass::new(false, ranges))
+    }
+
+    /*
+    The ranges of symbols in the class. For a negated class these are
+    the ranges of every Unicode scalar value outside of the listed ones, except the virtual symbols.
+    */
+    pub fn positive_ranges(&self) -> Vec<(char, char)> {
+        if !self.negated {
+            return self.ranges.clone()
+        }
+
+        let mut ranges = vec![];
+        let mut start = Some('\0');
+        let excluded = CharClass::new(false, [self.ranges.clone(), vec![(LINE_START, TEXT_END)]].concat());
+        for &(range_start, range_end) in &excluded.ranges {
+            if let Some(gap_start) = start.filter(|&gap_start| gap_start < range_start) {
+                ranges.push((gap_start, char_before(range_start)));
+            }
+            start = char_after(range_end);
+        }
+        if let Some(gap_start) = start {
+            ranges.push((gap_start, char::MAX));
+        }
+        ranges
+    }
+
+    // Number of symbols in the ranges of the class, without regard for negation
+    pub fn len(&self) -> usize {
+        self.ranges.iter().map(|&(start, end)| (start..=end).count()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    // A negated class doesn't contain the virtual symbols of anchors, see LINE_START
+    pub fn contains(&self, symbol: char) -> bool {
+        let in_ranges = self.ranges.iter().any(|(start, end)| (start..=end).contains(&&symbol));
+        if self.negated { !in_ranges && !is_virtual(symbol) } else { in_ranges }
+    }
+}
+
+/*
+The anchors ^ and $ are matched by virtual symbols, which are not part of any text
+but are read by the automaton at the positions the anchors stand for.
+    LINE_START is read before the first symbol of every line, ^ matches it.
+    LINE_END is read after the last symbol of every line, that is before every newline and at the end of the text, $ matches it.
+    TEXT_END is read after the last symbol of the text, after LINE_END, it is written \z.
+Whoever runs the automaton has to read them, like Lexer does. They are noncharacters,
+so they can't clash with the symbols of a text, and negated classes and the wildcard don't match them.
+*/
+pub const LINE_START: char = '\u{FDD0}';
+pub const LINE_END: char = '\u{FDD1}';
+pub const TEXT_END: char = '\u{FDD2}';
+
+pub fn is_virtual(symbol: char) -> bool {
+    (LINE_START..=TEXT_END).contains(&symbol)
+}
+
+// The other upper and lower case forms of a symbol, if they are single symbols
+fn case_variants(symbol: char) -> Vec<char> {
+    let mut variants = vec![];
+    for variant in [symbol.to_lowercase().collect_vec(), symbol.to_uppercase().collect_vec()] {
+        if let [variant] = variant[..] {
+            if variant != symbol && !variants.contains(&variant) {
+                variants.push(variant)
+            }
+        }
+    }
+    variants
+}
+
+// The symbols right before and right after another one, skipping the surrogate code points
+fn char_before(symbol: char) -> char {
+    match symbol {
+        '\u{E000}' => '\u{D7FF}',
+        _ => char::from_u32(symbol as u32 - 1).unwrap(),
+    }
+}
+
+fn char_after(symbol: char) -> Option<char> {
+    match symbol {
+        '\u{D7FF}' => Some('\u{E000}'),
+        char::MAX => None,
+        _ => char::from_u32(symbol as u32 + 1),
+    }
+}
+
+impl Regex {
+    pub(crate) const MAX_INFERRED_CLASS_SIZE: usize = 4096;
+
+    /*
+    The symbols written in the regex: its literals and the symbols of its classes that are not negated, sorted.
+    What a negated class or a wildcard matches is "everything else", which can't be inferred from the regex,
+    so those symbols have to be given explicitly. The same goes for classes larger than MAX_INFERRED_CLASS_SIZE,
+    and for a complement, which is only taken over the symbols of the alphabet.
+    */
+    pub fn symbols(&self) -> Vec<char> {
+        let mut symbols = vec![];
+        self.collect_symbols(&mut symbols);
+        symbols.sort();
+        symbols.dedup();
+        symbols
+    }
+
+    fn collect_symbols(&self, symbols: &mut Vec<char>) {
+        match self {
+            Regex::Empty | Regex::Epsilon => (),
+            Regex::Literal(symbol) => symbols.push(*symbol),
+            Regex::Class(class) if class.negated || class.len() > Self::MAX_INFERRED_CLASS_SIZE => (),
+            Regex::Class(class) => class.ranges.iter().for_each(|&(start, end)| symbols.extend(start..=end)),
+            Regex::Concat(items) | Regex::Alt(items) | Regex::Intersect(items) => {
+                items.iter().for_each(|item| item.collect_symbols(symbols))
+            },
+            Regex::Star(inner) | Regex::Plus(inner) | Regex::Optional(inner) | Regex::Repeat { inner, .. } | Regex::Complement(inner) => {
+                inner.collect_symbols(symbols)
+            }
+        }
+    }
+
+    /*
+    The number of nodes of the regex once every bounded repetition is expanded into copies.
+    This is proportional to the number of states of the NFA built from it.
+    */
+    pub fn size(&self) -> usize {
+        match self {
+            Regex::Empty | Regex::Epsilon | Regex::Literal(_) | Regex::Class(_) => 1,
+            Regex::Concat(items) | Regex::Alt(items) | Regex::Intersect(items) => 1 + items.iter().map(Regex::size).sum::<usize>(),
+            Regex::Star(inner) | Regex::Plus(inner) | Regex::Optional(inner) | Regex::Complement(inner) => 1 + inner.size(),
+            Regex::Repeat { inner, min, max } => {
+                1 + inner.size().saturating_mul(max.unwrap_or(min + 1))
+            }
+        }
+    }
+
+    // precedence used to decide where parentheses are needed when printing
+    fn prec(&self) -> usize {
+        match self {
+            Regex::Alt(items) if items.len() > 1 => 0,
+            Regex::Intersect(items) if items.len() > 1 => 1,
+            Regex::Concat(items) if items.len() > 1 => 2,
+            Regex::Complement(_) => 3,
+            Regex::Star(_) | Regex::Plus(_) | Regex::Optional(_) | Regex::Repeat { .. } => 4,
+            _ => 5,
+        }
+    }
+
+    fn fmt_with_prec(&self, f: &mut fmt::Formatter<'_>, prec: usize) -> fmt::Result {
+        if self.prec() < prec {
+            write!(f, "(")?;
+            self.fmt_with_prec(f, 0)?;
+            return write!(f, ")")
+        }
+
+        match self {
+            Regex::Empty => write!(f, "[]"),
+            Regex::Epsilon => write!(f, "\\e"),
+            Regex::Literal(LINE_START) => write!(f, "^"),
+            Regex::Literal(LINE_END) => write!(f, "$"),
+            Regex::Literal(symbol) => write_symbol(f, *symbol, Parser::METACHARACTERS),
+            Regex::Class(class) => {
+                write!(f, "[")?;
+                if class.negated {
+                    write!(f, "^")?;
+                }
+                for &(start, end) in &class.ranges {
+                    write_symbol(f, start, Parser::CLASS_METACHARACTERS)?;
+                    if start != end {
+                        write!(f, "-")?;
+                        write_symbol(f, end, Parser::CLASS_METACHARACTERS)?;
+                    }
+                }
+                write!(f, "]")
+            },
+            Regex::Concat(items) if items.is_empty() => write!(f, "\\e"),
+            Regex::Concat(items) => items.iter().try_for_each(|item| item.fmt_with_prec(f, 3)),
+            Regex::Alt(items) if items.is_empty() => write!(f, "[]"),
+            Regex::Alt(items) => write_separated(f, items, "|", 1),
+            Regex::Intersect(items) if items.is_empty() => write!(f, "~[]"),
+            Regex::Intersect(items) => write_separated(f, items, "&", 2),
+            Regex::Complement(inner) => {
+                write!(f, "~")?;
+                inner.fmt_with_prec(f, 3)
+            },
+            Regex::Star(inner) => {
+                inner.fmt_with_prec(f, 5)?;
+                write!(f, "*")
+            },
+            Regex::Plus(inner) => {
+                inner.fmt_with_prec(f, 5)?;
+                write!(f, "+")
+            },
+            Regex::Optional(inner) => {
+                inner.fmt_with_prec(f, 5)?;
+                write!(f, "?")
+            },
+            Regex::Repeat { inner, min, max } => {
+                inner.fmt_with_prec(f, 5)?;
+                match max {
+                    Some(max) if max == min => write!(f, "{{{min}}}"),
+                    Some(max) => write!(f, "{{{min},{max}}}"),
+                    None => write!(f, "{{{min},}}"),
+                }
+            },
+        }
+    }
+}
+
+fn write_separated(f: &mut fmt::Formatter<'_>, items: &[Regex], separator: &str, prec: usize) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            write!(f, "{separator}")?;
+        }
+        item.fmt_with_prec(f, prec)?;
+    }
+    Ok(())
+}
+
+fn write_symbol(f: &mut fmt::Formatter<'_>, symbol: char, metacharacters: &str) -> fmt::Result {
+    match symbol {
+        '\n' => write!(f, "\\n"),
+        '\t' => write!(f, "\\t"),
+        '\r' => write!(f, "\\r"),
+        TEXT_END => write!(f, "\\z"),
+        // control, private use and other invisible symbols are written by their code
+        _ if symbol.is_control() || symbol.is_whitespace() && symbol != ' ' || is_private_use(symbol) || is_virtual(symbol) => {
+            write!(f, "\\u{{{:x}}}", symbol as u32)
+        },
+        _ if metacharacters.contains(symbol) => write!(f, "\\{symbol}"),
+        _ => write!(f, "{symbol}"),
+    }
+}
+
+fn is_private_use(symbol: char) -> bool {
+    ('\u{E000}'..='\u{F8FF}').contains(&symbol) || symbol >= '\u{F0000}'
+}
+
+/*
+Prints the regex back in the syntax accepted by the parser, with only the parentheses that are needed.
+*/
+impl fmt::Display for Regex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_with_prec(f, 0)
+    }
+}
+
+/*
+Named sub-patterns, like the definitions section of a flex spec.
+A regex uses a definition by writing its name in braces, as in {DIGIT}+ after defining DIGIT as [0-9].
+Definitions can use other definitions, in any order, as long as no definition ends up using itself.
+They are expanded when a regex that uses them is parsed.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct Definitions {
+    patterns: HashMap<String, String>,
+}
+
+impl Definitions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Names are made of letters, digits and '_', and don't start with a digit
+    pub fn define(&mut self, name: &str, pattern: &str) -> Result<(), RegexError> {
+        let valid_start = name.starts_with(|symbol: char| symbol.is_ascii_alphabetic() || symbol == '_');
+        if !valid_start || !name.chars().all(|symbol| symbol.is_ascii_alphanumeric() || symbol == '_') {
+            return Err(RegexError::new(RegexErrorKind::InvalidDefinitionName(name.to_string()), name, 0))
+        }
+        self.patterns.insert(name.to_string(), pattern.to_string());
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.patterns.get(name).map(String::as_str)
+    }
+}
+
+pub fn parse(pattern: &str, options: &RegexOptions) -> Result<Regex, RegexError> {
+    Parser::new(pattern, options).parse()
+}
+
+/*
+Split a lexer rule r/s into the regex r and its trailing context s, at the first '/' that is not
+escaped or inside parentheses, a class, a bounded repetition or a reference. Returns None for rules without one.
+*/
+pub fn split_trailing_context(pattern: &str) -> Option<(&str, &str)> {
+    let mut depth = 0;
+    let mut chars = pattern.char_indices().peekable();
+    while let Some((offset, symbol)) = chars.next() {
+        match symbol {
+            '\\' => { chars.next(); },
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            // skip to the end of the class, a ']' right after the opening bracket closes it
+            '[' => {
+                chars.next_if(|&(_, symbol)| symbol == '^');
+                while let Some((_, symbol)) = chars.next() {
+                    match symbol {
+                        '\\' => { chars.next(); },
+                        ']' => break,
+                        _ => (),
+                    }
+                }
+            },
+            '{' => { chars.find(|&(_, symbol)| symbol == '}'); },
+            '/' if depth == 0 => return Some((&pattern[..offset], &pattern[offset + 1..])),
+            _ => (),
+        }
+    }
+    None
+}
+
+// A parsed regex with the alphabet it is read over
+pub(crate) struct ParsedRegex {
+    pub(crate) tree: Regex,
+    pub(crate) alphabet: Vec<char>,
+    // the '&' and '~' operators of the regex with their offsets
+    pub(crate) boolean_operators: Vec<(char, usize)>,
+}
+
+/*
+Parse a regex written over the given alphabet. When a symbol of the alphabet is matched case-insensitively,
+its other case is added to the alphabet. Literals outside of the alphabet are reported as errors,
+whether they are matched case-insensitively or not.
+*/
+pub(crate) fn parse_with_alphabet(pattern: &str, alphabet: &str, options: &RegexOptions) -> Result<ParsedRegex, RegexError> {
+    let mut parser = Parser::new(pattern, options);
+    let tree = parser.parse()?;
+    let alphabet: Vec<char> = alphabet.chars().chain(parser.extra_symbols).unique().collect();
+    let variants = parser.case_variants.iter().filter(|(symbol, _)| alphabet.contains(symbol)).map(|&(_, variant)| variant).collect_vec();
+    let alphabet: Vec<char> = alphabet.into_iter().chain(variants).unique().collect();
+    if let Some(&(symbol, offset)) = parser.literals.iter().find(|(symbol, _)| !alphabet.contains(symbol)) {
+        return Err(RegexError::new(RegexErrorKind::SymbolNotInAlphabet(symbol), pattern, offset))
+    }
+    let (tree, alphabet) = if options.utf8 {
+        (tree.to_utf8(), alphabet.into_iter().flat_map(utf8::encode).unique().collect())
+    } else {
+        (tree, alphabet)
+    };
+
+    Ok(ParsedRegex { tree, alphabet, boolean_operators: parser.boolean_operators })
+}
+
+/*
+Parse a regex and infer its alphabet from the symbols it uses, see Regex::symbols.
+In UTF-8 mode every class is turned into bytes, so the inferred alphabet has every byte the regex can read.
+Otherwise a class too large to be inferred is an error unless some of its symbols are in the alphabet, like the extra symbols.
+*/
+pub(crate) fn parse_with_inferred_alphabet(pattern: &str, extra_symbols: &str, options: &RegexOptions) -> Result<ParsedRegex, RegexError> {
+    let mut parser = Parser::new(pattern, options);
+    let tree = parser.parse()?;
+    let (tree, alphabet) = if options.utf8 {
+        let tree = tree.to_utf8();
+        let alphabet = tree.symbols().into_iter().chain(extra_symbols.chars().flat_map(utf8::encode)).unique().collect();
+        (tree, alphabet)
+    } else {
+        let alphabet: Vec<char> = tree.symbols().into_iter().chain(extra_symbols.chars()).chain(parser.extra_symbols)
+            .chain(parser.case_variants.into_iter().map(|(_, variant)| variant))
+            .unique().collect();
+        // a large class only matches the symbols of the alphabet, so without any of them it would match nothing
+        if let Some(&(_, offset)) = parser.large_classes.iter().find(|(class, _)| !alphabet.iter().any(|&symbol| class.contains(symbol))) {
+            return Err(RegexError::new(RegexErrorKind::ClassTooLargeToInfer(Regex::MAX_INFERRED_CLASS_SIZE), pattern, offset))
+        }
+        (tree, alphabet)
+    };
+
+    Ok(ParsedRegex { tree, alphabet, boolean_operators: parser.boolean_operators })
+}
+
+// What an escape sequence stands for
+enum Escape {
+    Epsilon,
+    Symbol(char),
+    Class(CharClass),
+}
+
+/*
+A recursive-descent parser for the regex syntax, following the grammar
+    alternation   := intersection ('|' intersection)*
+    intersection  := concatenation ('&' concatenation)*
+    concatenation := complement+
+    complement    := '~' complement | repetition
+    repetition    := atom ('*' | '+' | '?' | '{' bounds '}')*
+    atom          := symbol | '\' escape | '[' class ']' | '.' | '(' alternation ')' | '{' name '}'
+In the PosixExtended syntax there is no intersection or complement, a '{' always starts bounds,
+a '\' takes the next symbol literally and classes are read as bracket expressions, see parse_class.
+Besides the tree, the parser records the offset of every literal symbol,
+so symbols missing from an alphabet can be reported where they were written.
+Literals that come from a definition are recorded at the offset of the reference to it.
+The offsets of the '&' and '~' operators are recorded the same way, for constructions that can't handle them.
+*/
+pub(crate) struct Parser<'a> {
+    pattern: &'a str,
+    chars: Vec<(usize, char)>,
+    position: usize,
+    options: &'a RegexOptions,
+    // names of the definitions being expanded, from the outermost one
+    expanding: Vec<String>,
+    // flags in effect at the current position
+    case_insensitive: bool,
+    dot_matches_newline: bool,
+    pub(crate) literals: Vec<(char, usize)>,
+    // symbols to add to the alphabet: the virtual symbols of anchors
+    pub(crate) extra_symbols: Vec<char>,
+    // the other case of every symbol matched case-insensitively, with the symbol as written
+    pub(crate) case_variants: Vec<(char, char)>,
+    // classes with more than Regex::MAX_INFERRED_CLASS_SIZE symbols, with their offsets
+    pub(crate) large_classes: Vec<(CharClass, usize)>,
+    pub(crate) boolean_operators: Vec<(char, usize)>,
+}
+
+impl<'a> Parser<'a> {
+    // '/' isn't an operator, but it is escaped when printing because it separates the trailing context of a lexer rule
+    const METACHARACTERS: &'static str = "\\()[]{}|*+?.&~^$/";
+    const CLASS_METACHARACTERS: &'static str = "\\]-^";
+    // upper bound for the counts of a bounded repetition and for the size of the regex it expands to
+    pub(crate) const MAX_REPETITION: usize = 1000;
+    pub(crate) const MAX_REPETITION_SIZE: usize = 100_000;
+
+    pub(crate) fn new(pattern: &'a str, options: &'a RegexOptions) -> Self {
+        Parser {
+            pattern,
+            chars: pattern.char_indices().collect(),
+            position: 0,
+            options,
+            expanding: vec![],
+            case_insensitive: options.case_insensitive,
+            dot_matches_newline: options.dot_matches_newline,
+            literals: vec![],
+            extra_symbols: vec![],
+            case_variants: vec![],
+            large_classes: vec![],
+            boolean_operators: vec![],
+        }
+    }
+
+    pub(crate) fn parse(&mut self) -> Result<Regex, RegexError> {
+        let regex = self.parse_alternation()?;
+        match self.peek() {
+            None => Ok(regex),
+            Some((offset, _)) => Err(self.error(RegexErrorKind::UnmatchedCloseParenthesis, offset)),
+        }
+    }
+
+    fn error(&self, kind: RegexErrorKind, offset: usize) -> RegexError {
+        RegexError::new(kind, self.pattern, offset)
+    }
+
+    fn peek(&self) -> Option<(usize, char)> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Option<(usize, char)> {
+        let next = self.peek();
+        self.position += 1;
+        next
+    }
+
+    // offset of the next symbol, or the end of the pattern
+    fn offset(&self) -> usize {
+        self.peek().map_or(self.pattern.len(), |(offset, _)| offset)
+    }
+
+    fn posix(&self) -> bool {
+        self.options.syntax == Syntax::PosixExtended
+    }
+
+    // Whether the next symbol is an operator ending an operand, '&' only being one in the native syntax
+    fn at_operator(&self, symbol: char) -> bool {
+        symbol == '|' || symbol == ')' || symbol == '&' && !self.posix()
+    }
+
+    // Whether an operand ends at the next symbol
+    fn at_operand_end(&self) -> bool {
+        self.peek().is_none_or(|(_, symbol)| self.at_operator(symbol))
+    }
+
+    fn parse_alternation(&mut self) -> Result<Regex, RegexError> {
+        let mut branches = vec![self.parse_intersection()?];
+        while let Some((offset, '|')) = self.peek() {
+            self.next();
+            if self.at_operand_end() {
+                return Err(self.error(RegexErrorKind::MissingOperand, offset))
+            }
+            branches.push(self.parse_intersection()?);
+        }
+
+        Ok(if branches.len() == 1 { branches.pop().unwrap() } else { Regex::Alt(branches) })
+    }
+
+    fn parse_intersection(&mut self) -> Result<Regex, RegexError> {
+        let mut parts = vec![self.parse_concatenation()?];
+        while let Some((offset, '&')) = self.peek().filter(|_| !self.posix()) {
+            self.next();
+            self.boolean_operators.push(('&', offset));
+            if self.at_operand_end() {
+                return Err(self.error(RegexErrorKind::MissingOperand, offset))
+            }
+            parts.push(self.parse_concatenation()?);
+        }
+
+        Ok(if parts.len() == 1 { parts.pop().unwrap() } else { Regex::Intersect(parts) })
+    }
+
+    fn parse_concatenation(&mut self) -> Result<Regex, RegexError> {
+        let mut items = vec![];
+        while let Some((_, symbol)) = self.peek() {
+            if self.at_operator(symbol) {
+                break
+            }
+            items.push(self.parse_complement()?);
+        }
+
+        match (items.len(), self.peek()) {
+            (0, Some((offset, '|' | '&'))) => Err(self.error(RegexErrorKind::MissingOperand, offset)),
+            (0, _) => Err(self.error(RegexErrorKind::EmptyRegex, self.offset())),
+            (1, _) => Ok(items.pop().unwrap()),
+            _ => Ok(Regex::Concat(items)),
+        }
+    }
+
+    fn parse_complement(&mut self) -> Result<Regex, RegexError> {
+        match self.peek() {
+            Some((offset, '~')) if !self.posix() => {
+                self.next();
+                self.boolean_operators.push(('~', offset));
+                if self.at_operand_end() {
+                    return Err(self.error(RegexErrorKind::MissingOperand, offset))
+                }
+                Ok(Regex::Complement(Box::new(self.parse_complement()?)))
+            },
+            _ => self.parse_repetition(),
+        }
+    }
+
+    fn parse_repetition(&mut self) -> Result<Regex, RegexError> {
+        let mut regex = self.parse_atom()?;
+        while let Some((offset, symbol)) = self.peek() {
+            if !"*+?{".contains(symbol) || self.at_reference() {
+                break
+            }
+            self.next();
+            regex = match symbol {
+                '*' => Regex::Star(Box::new(regex)),
+                '+' => Regex::Plus(Box::new(regex)),
+                '?' => Regex::Optional(Box::new(regex)),
+                _ => {
+                    let (min, max) = self.parse_bounds(offset)?;
+                    let repeat = Regex::Repeat { inner: Box::new(regex), min, max };
+                    if repeat.size() > Self::MAX_REPETITION_SIZE {
+                        return Err(self.error(RegexErrorKind::RepetitionTooManyStates(Self::MAX_REPETITION_SIZE), offset))
+                    }
+                    repeat
+                }
+            };
+        }
+        Ok(regex)
+    }
+
+    fn parse_atom(&mut self) -> Result<Regex, RegexError> {
+        let (offset, symbol) = self.next().expect("parse_atom is only called before a symbol");
+        match symbol {
+            '(' => {
+                if self.peek().is_none() {
+                    return Err(self.error(RegexErrorKind::UnmatchedOpenParenthesis, offset))
+                }
+                // inline flags only apply inside of their group
+                let outer_flags = (self.case_insensitive, self.dot_matches_newline);
+                if !self.posix() && self.next_if(|symbol| symbol == '?').is_some() {
+                    self.parse_flags(offset)?;
+                }
+                let inner = self.parse_alternation()?;
+                (self.case_insensitive, self.dot_matches_newline) = outer_flags;
+                match self.next() {
+                    Some((_, ')')) => Ok(inner),
+                    _ => Err(self.error(RegexErrorKind::UnmatchedOpenParenthesis, offset)),
+                }
+            },
+            '{' if self.at_name() && !self.posix() => self.parse_reference(offset),
+            '*' | '+' | '?' | '{' => Err(self.error(RegexErrorKind::MissingOperand, offset)),
+            '\\' if self.posix() => match self.next() {
+                Some((_, symbol)) => Ok(self.literal(symbol, offset)),
+                None => Err(self.error(RegexErrorKind::UnfinishedEscape, offset)),
+            },
+            '\\' => match self.parse_escape(offset)? {
+                Escape::Epsilon => Ok(Regex::Epsilon),
+                Escape::Symbol(symbol) => Ok(self.literal(symbol, offset)),
+                Escape::Class(class) => Ok(Regex::Class(self.fold_class(class, offset))),
+            },
+            '[' => self.parse_class(offset),
+            // the wildcard is the class of every symbol, except newline unless the flags say otherwise
+            '.' if self.dot_matches_newline => Ok(Regex::Class(CharClass::new(true, vec![]))),
+            '.' => Ok(Regex::Class(CharClass::new(true, vec![('\n', '\n')]))),
+            '^' => {
+                self.extra_symbols.push(LINE_START);
+                Ok(Regex::Literal(LINE_START))
+            },
+            '$' => {
+                self.extra_symbols.push(LINE_END);
+                Ok(Regex::Literal(LINE_END))
+            },
+            _ => Ok(self.literal(symbol, offset)),
+        }
+    }
+
+    /*
+    Read the flags of a group starting right after its "(?" at the given offset, up to and including the ':'.
+    The flags are i for case-insensitive matching and s for a wildcard that matches newline,
+    flags after a '-' are turned off, as in (?i-s:...).
+    */
+    fn parse_flags(&mut self, open_offset: usize) -> Result<(), RegexError> {
+        let mut value = true;
+        loop {
+            match self.next() {
+                Some((_, ':')) => return Ok(()),
+                Some((_, '-')) if value => value = false,
+                Some((_, 'i')) => self.case_insensitive = value,
+                Some((_, 's')) => self.dot_matches_newline = value,
+                _ => return Err(self.error(RegexErrorKind::InvalidFlags, open_offset)),
+            }
+        }
+    }
+
+    // A literal symbol, or the class of its upper and lower case forms when matching case-insensitively
+    fn literal(&mut self, symbol: char, offset: usize) -> Regex {
+        let variants = case_variants(symbol);
+        self.literals.push((symbol, offset));
+        if !self.case_insensitive || variants.is_empty() {
+            return Regex::Literal(symbol)
+        }
+
+        self.case_variants.extend(variants.iter().map(|&variant| (symbol, variant)));
+        let ranges = variants.into_iter().chain([symbol]).map(|x| (x, x)).collect();
+        Regex::Class(CharClass::new(false, ranges))
+    }
+
+    // Add the upper and lower case forms of the symbols of a class when matching case-insensitively
+    fn fold_class(&mut self, class: CharClass, offset: usize) -> CharClass {
+        if !class.negated && class.len() > Regex::MAX_INFERRED_CLASS_SIZE {
+            self.large_classes.push((class.clone(), offset));
+        }
+        if !self.case_insensitive || class.len() > Regex::MAX_INFERRED_CLASS_SIZE {
+            return class
+        }
+
+        let variants = class.ranges.iter()
+            .flat_map(|&(start, end)| start..=end)
+            .flat_map(|symbol| case_variants(symbol).into_iter().map(move |variant| (symbol, variant)))
+            .collect_vec();
+        self.case_variants.extend(&variants);
+        let ranges = class.ranges.iter().copied().chain(variants.into_iter().map(|(_, x)| (x, x))).collect();
+        CharClass::new(class.negated, ranges)
+    }
+
+    // Whether the next symbol opens a reference to a definition, instead of a bounded repetition
+    fn at_reference(&self) -> bool {
+        !self.posix() && self.peek().is_some_and(|(_, symbol)| symbol == '{') && self.chars.get(self.position + 1).is_some_and(|&(_, symbol)| {
+            symbol.is_ascii_alphabetic() || symbol == '_'
+        })
+    }
+
+    fn at_name(&self) -> bool {
+        self.peek().is_some_and(|(_, symbol)| symbol.is_ascii_alphabetic() || symbol == '_')
+    }
+
+    /*
+    Read a reference to a definition starting right after its opening brace at the given offset,
+    and parse the pattern of the definition in its place.
+    Errors in the definition are wrapped in InDefinition and reported at the opening brace.
+    */
+    fn parse_reference(&mut self, open_offset: usize) -> Result<Regex, RegexError> {
+        let name: String = iter::from_fn(|| self.next_if(|symbol| symbol.is_ascii_alphanumeric() || symbol == '_')).collect();
+        if self.next_if(|symbol| symbol == '}').is_none() {
+            return Err(self.error(RegexErrorKind::UnclosedReference, open_offset))
+        }
+        if self.expanding.contains(&name) {
+            return Err(self.error(RegexErrorKind::DefinitionCycle(name), open_offset))
+        }
+        let Some(pattern) = self.options.definitions.get(&name) else {
+            return Err(self.error(RegexErrorKind::UndefinedName(name), open_offset))
+        };
+
+        let mut parser = Parser::new(pattern, self.options);
+        parser.expanding = [self.expanding.clone(), vec![name.clone()]].concat();
+        (parser.case_insensitive, parser.dot_matches_newline) = (self.case_insensitive, self.dot_matches_newline);
+        // an error in the definition is reported at the reference, with where it is in the definition
+        let regex = parser.parse().map_err(|error| {
+            self.error(RegexErrorKind::InDefinition(name, error.offset, Box::new(error.kind)), open_offset)
+        })?;
+        self.literals.extend(parser.literals.into_iter().map(|(symbol, _)| (symbol, open_offset)));
+        self.extra_symbols.extend(parser.extra_symbols);
+        self.case_variants.extend(parser.case_variants);
+        self.large_classes.extend(parser.large_classes.into_iter().map(|(class, _)| (class, open_offset)));
+        self.boolean_operators.extend(parser.boolean_operators.into_iter().map(|(operator, _)| (operator, open_offset)));
+
+        Ok(regex)
+    }
+
+    /*
+    Read a character class starting right after its opening bracket at the given offset.
+    A class is a list of symbols, ranges and shorthand classes such as [A-Za-z_0-9] or [\d_].
+    If the class starts with ^ it is negated and matches every symbol of the alphabet not listed.
+    [] is the empty class that matches nothing and [^] matches any symbol.
+    A '-' at either end is taken literally, any other symbol can be escaped with '\'.
+
+    In the PosixExtended syntax the class is a bracket expression instead: a ']' right after the opening bracket
+    or its ^ is taken literally, a '\' is an ordinary symbol, and [:name:] adds a class such as [:alpha:], see CharClass::posix.
+    */
+    fn parse_class(&mut self, open_offset: usize) -> Result<Regex, RegexError> {
+        let negated = self.peek().is_some_and(|(_, symbol)| symbol == '^');
+        if negated {
+            self.next();
+        }
+
+        let first_position = self.position;
+        let mut ranges: Vec<(char, char)> = vec![];
+        loop {
+            let (offset, start) = match self.next() {
+                Some(symbol @ (_, ']')) if self.posix() && self.position == first_position + 1 => symbol,
+                Some((_, ']')) => break,
+                Some((offset, '[')) if self.posix() && self.peek().is_some_and(|(_, symbol)| symbol == ':') => {
+                    ranges.extend(self.parse_posix_class(offset)?.ranges);
+                    continue
+                },
+                Some((offset, '\\')) if !self.posix() => match self.parse_class_escape(offset)? {
+                    Escape::Class(class) => {
+                        ranges.extend(class.positive_ranges());
+                        continue
+                    },
+                    Escape::Symbol(symbol) => (offset, symbol),
+                    Escape::Epsilon => unreachable!("epsilon is rejected inside classes"),
+                },
+                Some(symbol) => symbol,
+                None => return Err(self.error(RegexErrorKind::UnclosedClass, open_offset)),
+            };
+
+            let mut end = start;
+            let next_is_dash = self.peek().is_some_and(|(_, symbol)| symbol == '-');
+            let dash_is_last = matches!(self.chars.get(self.position + 1), Some((_, ']')) | None);
+            if next_is_dash && !dash_is_last {
+                self.next();
+                end = match self.next() {
+                    Some((escape_offset, '\\')) if !self.posix() => match self.parse_class_escape(escape_offset)? {
+                        Escape::Symbol(symbol) => symbol,
+                        _ => return Err(self.error(RegexErrorKind::InvalidEscape, escape_offset)),
+                    },
+                    Some((_, symbol)) => symbol,
+                    None => return Err(self.error(RegexErrorKind::UnclosedClass, open_offset)),
+                };
+                if end < start {
+                    return Err(self.error(RegexErrorKind::InvalidRange(start, end), offset))
+                }
+            }
+            ranges.push((start, end));
+        }
+
+        Ok(Regex::Class(self.fold_class(CharClass::new(negated, ranges), open_offset)))
+    }
+
+    // Read a class [:name:] of a bracket expression, starting right after the '[' at the given offset
+    fn parse_posix_class(&mut self, offset: usize) -> Result<CharClass, RegexError> {
+        self.next();
+        let name: String = iter::from_fn(|| self.next_if(|symbol| symbol.is_ascii_alphabetic())).collect();
+        if self.next_if(|symbol| symbol == ':').is_none() || self.next_if(|symbol| symbol == ']').is_none() {
+            return Err(self.error(RegexErrorKind::UnclosedClass, offset))
+        }
+        CharClass::posix(&name).ok_or_else(|| self.error(RegexErrorKind::UnknownCharacterClass(name), offset))
+    }
+
+    fn parse_class_escape(&mut self, offset: usize) -> Result<Escape, RegexError> {
+        match self.parse_escape(offset)? {
+            Escape::Epsilon => Err(self.error(RegexErrorKind::InvalidEscape, offset)),
+            escape => Ok(escape),
+        }
+    }
+
+    /*
+    Read an escape sequence starting right after the '\' at the given offset.
+        \e is epsilon
+        \n, \t and \r are newline, tab and carriage return
+        \z is the virtual symbol read at the end of the text, see TEXT_END
+        \xNN is the symbol with the hexadecimal code NN and \u{N...} the one with code N... (1 to 6 digits)
+        \d, \w and \s are the digit, word and whitespace classes, \D, \W and \S are their negations
+        \p{..} is the class of a Unicode general category such as \p{Lu} or \p{L}, also written \pL, and \P{..} is its negation
+    Any other escaped symbol is taken literally.
+    */
+    fn parse_escape(&mut self, offset: usize) -> Result<Escape, RegexError> {
+        let Some((_, symbol)) = self.next() else {
+            return Err(self.error(RegexErrorKind::UnfinishedEscape, offset))
+        };
+
+        let escape = match symbol {
+            'e' => Escape::Epsilon,
+            'n' => Escape::Symbol('\n'),
+            't' => Escape::Symbol('\t'),
+            'r' => Escape::Symbol('\r'),
+            'z' => {
+                self.extra_symbols.push(TEXT_END);
+                Escape::Symbol(TEXT_END)
+            },
+            'x' => {
+                let digits: String = (0..2).filter_map(|_| self.next_if(|symbol| symbol.is_ascii_hexdigit())).collect();
+                match (digits.len(), u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32)) {
+                    (2, Some(symbol)) => Escape::Symbol(symbol),
+                    _ => return Err(self.error(RegexErrorKind::InvalidEscape, offset)),
+                }
+            },
+            'u' => {
+                if self.next_if(|symbol| symbol == '{').is_none() {
+                    return Err(self.error(RegexErrorKind::InvalidEscape, offset))
+                }
+                let digits: String = std::iter::from_fn(|| self.next_if(|symbol| symbol.is_ascii_hexdigit())).collect();
+                let closed = self.next_if(|symbol| symbol == '}').is_some();
+                match u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32) {
+                    Some(symbol) if closed && digits.len() <= 6 => Escape::Symbol(symbol),
+                    _ => return Err(self.error(RegexErrorKind::InvalidEscape, offset)),
+                }
+            },
+            'd' | 'w' | 's' | 'D' | 'W' | 'S' => Escape::Class(CharClass::shorthand(symbol)),
+            'p' | 'P' => Escape::Class(self.parse_property(offset, symbol == 'P')?),
+            _ => Escape::Symbol(symbol),
+        };
+        Ok(escape)
+    }
+
+    fn parse_property(&mut self, offset: usize, negated: bool) -> Result<CharClass, RegexError> {
+        let name: String = if self.next_if(|symbol| symbol == '{').is_some() {
+            let name = iter::from_fn(|| self.next_if(|symbol| symbol.is_ascii_alphanumeric() || symbol == '_')).collect();
+            if self.next_if(|symbol| symbol == '}').is_none() {
+                return Err(self.error(RegexErrorKind::InvalidEscape, offset))
+            }
+            name
+        } else {
+            match self.next_if(|symbol| symbol.is_ascii_alphabetic()) {
+                Some(letter) => letter.to_string(),
+                None => return Err(self.error(RegexErrorKind::InvalidEscape, offset)),
+            }
+        };
+
+        CharClass::general_category(&name, negated).ok_or_else(|| self.error(RegexErrorKind::UnknownProperty(name), offset))
+    }
+
+    fn next_if(&mut self, predicate: impl Fn(char) -> bool) -> Option<char> {
+        let (_, symbol) = self.peek().filter(|&(_, symbol)| predicate(symbol))?;
+        self.position += 1;
+        Some(symbol)
+    }
+
+    /*
+    Read the bounds of a bounded repetition starting right after its opening brace at the given offset.
+    The accepted forms are {m} for exactly m, {m,} for at least m and {m,n} for m up to n repetitions.
+    */
+    fn parse_bounds(&mut self, open_offset: usize) -> Result<(usize, Option<usize>), RegexError> {
+        let Some(min) = self.parse_count()? else {
+            return Err(self.error(RegexErrorKind::InvalidRepetition, open_offset))
+        };
+        let max = match self.next() {
+            Some((_, '}')) => return Ok((min, Some(min))),
+            Some((_, ',')) => self.parse_count()?,
+            Some(_) => return Err(self.error(RegexErrorKind::InvalidRepetition, open_offset)),
+            None => return Err(self.error(RegexErrorKind::UnclosedRepetition, open_offset)),
+        };
+        match self.next() {
+            Some((_, '}')) => (),
+            Some(_) => return Err(self.error(RegexErrorKind::InvalidRepetition, open_offset)),
+            None => return Err(self.error(RegexErrorKind::UnclosedRepetition, open_offset)),
+        }
+        if let Some(max) = max.filter(|&max| max < min) {
+            return Err(self.error(RegexErrorKind::InvalidRepetitionBounds(min, max), open_offset))
+        }
+
+        Ok((min, max))
+    }
+
+    fn parse_count(&mut self) -> Result<Option<usize>, RegexError> {
+        let offset = self.offset();
+        let mut digits = String::new();
+        while let Some((_, digit)) = self.peek().filter(|(_, symbol)| symbol.is_ascii_digit()) {
+            digits.push(digit);
+            self.next();
+        }
+        if digits.is_empty() {
+            return Ok(None)
+        }
+        match digits.parse::<usize>() {
+            Ok(count) if count <= Self::MAX_REPETITION => Ok(Some(count)),
+            _ => Err(self.error(RegexErrorKind::RepetitionTooLarge(Self::MAX_REPETITION), offset))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use crate::{dfa::Dfa, lexer::Lexer, nfa::Nfa};
+
+    use super::*;
+
+    /*
+    A case of the conformance table for the PosixExtended syntax:
+    a pattern with strings it matches and strings it doesn't match, each string taken as a whole.
+    */
+    struct Case {
+        pattern: &'static str,
+        matches: &'static [&'static str],
+        rejects: &'static [&'static str],
+    }
+
+    const CASES: &[Case] = &[
+        Case { pattern: "abc", matches: &["abc"], rejects: &["", "ab", "abcd"] },
+        Case { pattern: "a|b|c", matches: &["a", "b", "c"], rejects: &["", "ab"] },
+        Case { pattern: "a|b*", matches: &["a", "", "bb"], rejects: &["ab"] },
+        Case { pattern: "ab*c", matches: &["ac", "abc", "abbbc"], rejects: &["a", "abdc"] },
+        Case { pattern: "ab+c", matches: &["abc", "abbc"], rejects: &["ac"] },
+        Case { pattern: "ab?c", matches: &["ac", "abc"], rejects: &["abbc"] },
+        Case { pattern: "(a*)*", matches: &["", "aaa"], rejects: &["b"] },
+        Case { pattern: "(ab|cd)+", matches: &["ab", "abcd", "cdab"], rejects: &["", "abc"] },
+        Case { pattern: "a{2}", matches: &["aa"], rejects: &["a", "aaa"] },
+        Case { pattern: "a{2,}", matches: &["aa", "aaaa"], rejects: &["a"] },
+        Case { pattern: "a{1,3}", matches: &["a", "aaa"], rejects: &["", "aaaa"] },
+        Case { pattern: "a{0}b", matches: &["b"], rejects: &["ab"] },
+        Case { pattern: "a.c", matches: &["abc", "a.c"], rejects: &["ac"] },
+        Case { pattern: "^abc$", matches: &["abc"], rejects: &["xabc", "abcx"] },
+        Case { pattern: "[abc]+", matches: &["cab"], rejects: &["abd"] },
+        Case { pattern: "[^abc]", matches: &["d", "1"], rejects: &["", "a"] },
+        Case { pattern: "[a-z0-9]+", matches: &["az09"], rejects: &["A"] },
+        Case { pattern: "[]a]", matches: &["]", "a"], rejects: &["b"] },
+        Case { pattern: "[^]a]", matches: &["b"], rejects: &["]", "a"] },
+        Case { pattern: "[a-]", matches: &["a", "-"], rejects: &["b"] },
+        Case { pattern: "[.]", matches: &["."], rejects: &["a"] },
+        Case { pattern: "[*+?{|]+", matches: &["*+?{|"], rejects: &["a"] },
+        Case { pattern: r"[\]", matches: &["\\"], rejects: &["]"] },
+        Case { pattern: "[[:alpha:]]+", matches: &["abcXYZ"], rejects: &["ab1"] },
+        Case { pattern: "[[:digit:][:upper:]]+", matches: &["A1B2"], rejects: &["a"] },
+        Case { pattern: "[^[:space:]]+", matches: &["a-b"], rejects: &["a b"] },
+        Case { pattern: "[[:xdigit:]]{2}", matches: &["fF", "09"], rejects: &["fg"] },
+        Case { pattern: "[[:punct:]]", matches: &["!", "~", "["], rejects: &["a", " "] },
+        Case { pattern: "[[:alpha:]_][[:alnum:]_]*", matches: &["_x1", "a"], rejects: &["1a"] },
+        Case { pattern: r"a\.b", matches: &["a.b"], rejects: &["axb"] },
+        Case { pattern: r"\(a\)", matches: &["(a)"], rejects: &["a"] },
+        Case { pattern: r"a\{2\}", matches: &["a{2}"], rejects: &["aa"] },
+        Case { pattern: r"\\n", matches: &["\\n"], rejects: &["\n"] },
+        Case { pattern: "a&b", matches: &["a&b"], rejects: &["a"] },
+        Case { pattern: "~a", matches: &["~a"], rejects: &["b"] },
+    ];
+
+    // Patterns that are not valid EREs and must be rejected
+    const INVALID: &[&str] = &["a(b", "()", "*a", "a|*", "a{2,1}", "a{1", "[a", "[[:alpha:]", "[[:foo:]]", "a\\"];
+
+    /*
+    Whether the DFA of a pattern matches all of a string, with the anchors ^ and $ matching at its ends,
+    that is with the virtual symbol LINE_START before it and LINE_END and TEXT_END after it.
+    */
+    fn matches_whole(dfa: &Dfa, text: &str) -> bool {
+        let ends: [&[char]; 3] = [&[], &[LINE_END], &[LINE_END, TEXT_END]];
+        [false, true].into_iter().cartesian_product(ends).any(|(line_start, end)| {
+            let start = if line_start { Some(LINE_START) } else { None };
+            dfa.accepts(start.into_iter().chain(text.chars()).chain(end.iter().copied()).collect())
+        })
+    }
+
+    #[test]
+    fn posix_conformance() {
+        let options = RegexOptions { syntax: Syntax::PosixExtended, ..RegexOptions::default() };
+        for case in CASES {
+            let texts = case.matches.iter().chain(case.rejects).join("");
+            let dfa = Dfa::from_regex_inferred_with_options(case.pattern, &texts, 1, &options).unwrap();
+            for text in case.matches {
+                assert!(matches_whole(&dfa, text), "{} should match {:?}", case.pattern, text);
+            }
+            for text in case.rejects {
+                assert!(!matches_whole(&dfa, text), "{} should not match {:?}", case.pattern, text);
+            }
+        }
+    }
+
+    #[test]
+    fn posix_invalid_patterns_are_rejected() {
+        let options = RegexOptions { syntax: Syntax::PosixExtended, ..RegexOptions::default() };
+        for pattern in INVALID {
+            assert!(Dfa::from_regex_inferred_with_options(pattern, "", 1, &options).is_err(), "{pattern} should be rejected");
+        }
+    }
+
+    #[test]
+    fn printed_slash_is_not_trailing_context() {
+        let regex = Regex::Concat(vec![Regex::Literal('a'), Regex::Plus(Box::new(Regex::Literal('/'))), Regex::Literal('b')]);
+        let printed = regex.to_string();
+        assert_eq!(printed, r"a\/+b");
+        assert_eq!(split_trailing_context(&printed), None);
+        assert_eq!(parse(&printed, &RegexOptions::default()), Ok(regex));
+
+        let lexer = Lexer::new(&[("path", &printed)], &RegexOptions::default()).unwrap();
+        let tokens = lexer.tokenize(VecDeque::from(b"a//b".to_vec()));
+        assert_eq!(tokens.iter().map(|token| token.text.as_str()).collect_vec(), ["a//b"]);
+    }
+
+    #[test]
+    fn case_insensitive_literal_must_be_in_the_alphabet() {
+        let options = RegexOptions { case_insensitive: true, ..RegexOptions::default() };
+        let error = Nfa::from_regex_with_options("a", "b", 1, &options).unwrap_err();
+        assert_eq!(error.kind, RegexErrorKind::SymbolNotInAlphabet('a'));
+
+        let nfa = Nfa::from_regex_with_options("a", "a", 1, &options).unwrap();
+        assert!(nfa.accepts("a") && nfa.accepts("A"));
+
+        // a class only gets the other case of the symbols that are in the alphabet
+        let dfa = Dfa::from_regex_with_options("[ab]", "b", 1, &options).unwrap();
+        assert!(dfa.accepts("b".to_string()) && dfa.accepts("B".to_string()));
+        assert!(!dfa.accepts("a".to_string()) && !dfa.accepts("A".to_string()));
+    }
+
+    #[test]
+    fn large_class_needs_symbols_from_the_alphabet() {
+        let error = Dfa::from_regex_inferred(r"1|\p{L}+", "", 1).unwrap_err();
+        assert_eq!((error.kind, error.offset), (RegexErrorKind::ClassTooLargeToInfer(Regex::MAX_INFERRED_CLASS_SIZE), 2));
+
+        let dfa = Dfa::from_regex_inferred(r"\p{L}+", "abc", 1).unwrap();
+        assert!(dfa.accepts("abc".to_string()) && !dfa.accepts(String::new()));
+
+        let utf8 = RegexOptions { utf8: true, ..RegexOptions::default() };
+        let dfa = Dfa::from_regex_inferred_with_options(r"\p{L}+", "", 1, &utf8).unwrap();
+        assert!(dfa.accepts_bytes("abcé".as_bytes()));
+    }
+
+    fn with_definitions(definitions: &[(&str, &str)]) -> RegexOptions {
+        let mut options = RegexOptions::default();
+        for (name, pattern) in definitions {
+            options.definitions.define(name, pattern).unwrap();
+        }
+        options
+    }
+
+    #[test]
+    fn undefined_and_cyclic_definitions_are_errors() {
+        let options = with_definitions(&[("A", "a{B}"), ("B", "b|{A}"), ("C", "c{C}"), ("D", "{E}")]);
+        let error = parse("x{D}", &options).unwrap_err();
+        let undefined = RegexErrorKind::UndefinedName("E".to_string());
+        assert_eq!((error.kind, error.offset), (RegexErrorKind::InDefinition("D".to_string(), 0, Box::new(undefined)), 1));
+
+        let error = parse("{C}", &options).unwrap_err();
+        let cycle = RegexErrorKind::DefinitionCycle("C".to_string());
+        assert_eq!(error.kind, RegexErrorKind::InDefinition("C".to_string(), 1, Box::new(cycle)));
+
+        // the cycle A -> B -> A is found inside B
+        let error = parse("{A}", &options).unwrap_err();
+        let cycle = RegexErrorKind::DefinitionCycle("A".to_string());
+        let in_b = RegexErrorKind::InDefinition("B".to_string(), 2, Box::new(cycle));
+        assert_eq!(error.kind, RegexErrorKind::InDefinition("A".to_string(), 1, Box::new(in_b)));
+
+        let error = parse("{F}", &options).unwrap_err();
+        assert_eq!((error.kind, error.offset), (RegexErrorKind::UndefinedName("F".to_string()), 0));
+    }
+
+    #[test]
+    fn error_in_a_definition_is_reported_at_the_reference() {
+        let options = with_definitions(&[("X", "(a")]);
+        let error = parse("b{X}", &options).unwrap_err();
+        assert_eq!(error.pattern, "b{X}");
+        assert_eq!(error.offset, 1);
+        assert_eq!(error.render(), "b{X}\n ^ couldn't find matching closing parenthesis at offset 0 of definition X");
+    }
+}
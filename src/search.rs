@@ -0,0 +1,178 @@
+use std::ops::Range;
+
+use itertools::Itertools;
+
+use crate::{dfa::Dfa, error::RegexError, lexer::compile_anchored, nfa::{Nfa, RegexOptions}, regex::{parse, CharClass, LINE_END, LINE_START, TEXT_END}, utf8::byte_symbol};
+
+/*
+A searcher for the leftmost-longest matches of a regex anywhere in a text, like grep.
+The text is read as UTF-8 bytes, see RegexOptions::utf8, and matches are given by their byte ranges.
+The anchors work like in Lexer: ^ matches at the start of a match that starts a line, and $ before a newline
+or at the end of the text, without being part of the match.
+
+A search takes two passes over the text with two DFAs.
+    reverse: reads the text backwards from its end, and accepts at every position where a match starts.
+             It is the reverse of the DFA of ^?(r) followed by any bytes and the end of the text.
+    forward: the DFA of ^?(r), run from the leftmost of those positions to find the end of the longest match there.
+Both read the virtual symbols of the anchors where they hold, see Nfa::with_line_ends.
+*/
+pub struct Searcher {
+    forward: Dfa,
+    reverse: Dfa,
+}
+
+impl Searcher {
+    pub fn new(regex: &str, options: &RegexOptions) -> Result<Self, RegexError> {
+        let options = RegexOptions { utf8: true, ..options.clone() };
+        parse(regex, &options)?;
+        let forward = compile_anchored(regex, regex, 1, &options)?;
+
+        // what can follow a match up to the end of the text, unless the match reads the end of the text itself
+        let bytes = (0..=u8::MAX).map(byte_symbol).collect_vec();
+        let any_bytes = Nfa::class(&CharClass::new(false, vec![(byte_symbol(0), byte_symbol(u8::MAX))]), &bytes, 1).star();
+        let rest = any_bytes.concat(&Nfa::literal(TEXT_END, 1)).optional().with_line_ends();
+        let reverse = Dfa::from_nfa(&forward.concat(&rest).reverse());
+
+        Ok(Self { forward: Dfa::from_nfa(&forward), reverse })
+    }
+
+    // The leftmost-longest match in the text, if there is one
+    pub fn find(&self, text: &str) -> Option<Range<usize>> {
+        self.find_iter(text).into_iter().next()
+    }
+
+    /*
+    The leftmost-longest matches in the text that don't overlap, from left to right.
+    After an empty match the search goes on one char further, so it doesn't find the same match again.
+    */
+    pub fn find_iter(&self, text: &str) -> Vec<Range<usize>> {
+        let bytes = text.as_bytes();
+        let starts = self.match_starts(bytes);
+
+        let mut matches = vec![];
+        let mut position = 0;
+        while let Some(start) = (position..=bytes.len()).find(|&i| starts[i]) {
+            let end = self.longest_match_end(bytes, start).expect("a match starts at every position the reverse DFA accepts");
+            matches.push(start..end);
+            position = if end > start { end } else { start + text[start..].chars().next().map_or(1, char::len_utf8) };
+        }
+        matches
+    }
+
+    /*
+    Whether a match starts at each position of the text, found by reading it backwards with the reverse DFA.
+    The virtual symbols are read in the reverse order too: TEXT_END first, LINE_END after the newline it comes before,
+    and LINE_START last, only to check if a match starts there.
+    */
+    fn match_starts(&self, bytes: &[u8]) -> Vec<bool> {
+        let mut starts = vec![false; bytes.len() + 1];
+        let Some(mut state) = self.reverse.step(0, TEXT_END) else { return starts };
+
+        for position in (0..=bytes.len()).rev() {
+            if position < bytes.len() {
+                let Some(next) = self.reverse.step(state, byte_symbol(bytes[position])) else { break };
+                state = next;
+            }
+            let mut accepted = self.reverse.marks[state] > 0;
+            if is_line_end(bytes, position) {
+                state = self.reverse.step(state, LINE_END).unwrap_or(state);
+                accepted |= self.reverse.marks[state] > 0;
+            }
+            let line_start = self.reverse.step(state, LINE_START).filter(|_| is_line_start(bytes, position));
+            starts[position] = accepted || line_start.is_some_and(|state| self.reverse.marks[state] > 0);
+            if self.reverse.is_dead(state) {
+                break
+            }
+        }
+        starts
+    }
+
+    // The end of the longest match starting at the position, read with the forward DFA
+    fn longest_match_end(&self, bytes: &[u8], start: usize) -> Option<usize> {
+        let mut state = 0;
+        if is_line_start(bytes, start) {
+            state = self.forward.step(state, LINE_START).unwrap_or(state);
+        }
+
+        let mut end = None;
+        for position in start..=bytes.len() {
+            if position > start {
+                let Some(next) = self.forward.step(state, byte_symbol(bytes[position - 1])) else { return end };
+                state = next;
+            }
+            if self.forward.marks[state] > 0 {
+                end = Some(position);
+            }
+            if is_line_end(bytes, position) {
+                state = self.forward.step(state, LINE_END).unwrap_or(state);
+                if self.forward.marks[state] > 0 {
+                    end = Some(position);
+                }
+            }
+            if self.forward.is_dead(state) {
+                return end
+            }
+        }
+        if self.forward.step(state, TEXT_END).is_some_and(|state| self.forward.marks[state] > 0) {
+            end = Some(bytes.len());
+        }
+        end
+    }
+}
+
+fn is_line_start(bytes: &[u8], position: usize) -> bool {
+    position == 0 || bytes[position - 1] == b'\n'
+}
+
+fn is_line_end(bytes: &[u8], position: usize) -> bool {
+    position == bytes.len() || bytes[position] == b'\n'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(regex: &str, text: &str) -> Vec<Range<usize>> {
+        Searcher::new(regex, &RegexOptions::default()).unwrap().find_iter(text)
+    }
+
+    #[test]
+    fn finds_the_leftmost_longest_match() {
+        assert_eq!(matches("a|ab", "xab"), vec![1..3]);
+        assert_eq!(matches("ab|a", "xab"), vec![1..3]);
+        assert_eq!(matches("b+|ab", "abbb"), vec![0..2, 2..4]);
+        assert_eq!(matches("c", "ab"), vec![]);
+    }
+
+    #[test]
+    fn empty_matches_move_on_one_char() {
+        assert_eq!(matches("b*", "abba"), vec![0..0, 1..3, 3..3, 4..4]);
+        assert_eq!(matches("b*", ""), vec![0..0]);
+        assert_eq!(matches("b*", "é"), vec![0..0, 2..2]);
+    }
+
+    #[test]
+    fn anchors_match_at_line_boundaries() {
+        let text = "ab\nb\nab";
+        assert_eq!(matches("^a", text), vec![0..1, 5..6]);
+        assert_eq!(matches("b$", text), vec![1..2, 3..4, 6..7]);
+        assert_eq!(matches("^b$", text), vec![3..4]);
+        assert_eq!(matches("^", text), vec![0..0, 3..3, 5..5]);
+        assert_eq!(matches("$", text), vec![2..2, 4..4, 7..7]);
+        // the newline a $ stands before isn't part of the match
+        assert_eq!(matches("(a$)", "a\nb"), vec![0..1]);
+        assert_eq!(matches("a$|b", "a\nb"), vec![0..1, 2..3]);
+        assert_eq!(matches(r"b$\n", text), vec![1..3, 3..5]);
+        assert_eq!(matches(r"b\z", text), vec![6..7]);
+    }
+
+    #[test]
+    fn multibyte_text_gives_byte_ranges() {
+        let text = "añb€ñ";
+        assert_eq!(matches("ñ", text), vec![1..3, 7..9]);
+        assert_eq!(matches("[^a]b", text), vec![1..4]);
+        assert_eq!(matches(".€", text), vec![3..7]);
+        assert_eq!(matches("ñ$", text), vec![7..9]);
+        assert_eq!(matches("[a-zñ]+", text), vec![0..4, 7..9]);
+    }
+}
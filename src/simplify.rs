@@ -0,0 +1,208 @@
+use crate::{error::RegexError, nfa::{Nfa, RegexOptions}, regex::{ParsedRegex, parse_with_inferred_alphabet, CharClass, Regex}};
+
+/*
+The result of simplifying a regex, with the number of states of the NFA built from it before and after.
+*/
+#[derive(Debug, Clone)]
+pub struct Simplification {
+    pub original: Regex,
+    pub simplified: Regex,
+    pub states_before: usize,
+    pub states_after: usize,
+}
+
+/*
+Parse a regex and simplify it, see Regex::simplified.
+The alphabet used to count NFA states is inferred from the regex like in Nfa::from_regex_inferred.
+*/
+pub fn simplify(regex: &str, extra_symbols: &str, options: &RegexOptions) -> Result<Simplification, RegexError> {
+    let ParsedRegex { tree: original, alphabet, .. } = parse_with_inferred_alphabet(regex, extra_symbols, options)?;
+
+    let simplified = original.simplified();
+    let states_before = Nfa::from_tree(&original, alphabet.clone(), 1).marks.len();
+    let states_after = Nfa::from_tree(&simplified, alphabet, 1).marks.len();
+
+    Ok(Simplification { original, simplified, states_before, states_after })
+}
+
+impl Regex {
+    /*
+    An equivalent regex rewritten with the identities of Kleene algebra, where 0 is Empty and 1 is Epsilon:
+        0r = r0 = 0, 1r = r1 = r, 0|r = r, r|r = r, r|s = s|r
+        0* = 1* = 1, (r*)* = (r+)* = (r?)* = r*, (r|s*)* = (r|s)*
+        r+ = r* and r? = r when r matches the empty string
+        ~~r = r, 0&r = 0
+    Alternatives and intersections are flattened, sorted and deduplicated,
+    single symbol alternatives are merged into one class, and r r* and r* r become r+.
+    The result is a canonical form: equal results mean equivalent regexes, but not the other way around.
+    */
+    pub fn simplified(&self) -> Regex {
+        match self {
+            Regex::Empty | Regex::Epsilon | Regex::Literal(_) => self.clone(),
+            Regex::Class(class) => Self::class(class.clone()),
+            Regex::Concat(items) => Self::concat(items.iter().map(Regex::simplified).collect()),
+            Regex::Alt(items) => Self::alt(items.iter().map(Regex::simplified).collect()),
+            Regex::Star(inner) => Self::star(inner.simplified()),
+            Regex::Plus(inner) => Self::plus(inner.simplified()),
+            Regex::Optional(inner) => Self::optional(inner.simplified()),
+            Regex::Repeat { inner, min, max } => Self::repeat(inner.simplified(), *min, *max),
+            Regex::Intersect(parts) => Self::intersect(parts.iter().map(Regex::simplified).collect()),
+            Regex::Complement(inner) => Self::complement(inner.simplified()),
+        }
+    }
+
+    // Whether the regex matches the empty string
+    pub fn is_nullable(&self) -> bool {
+        match self {
+            Regex::Empty | Regex::Literal(_) | Regex::Class(_) => false,
+            Regex::Epsilon | Regex::Star(_) | Regex::Optional(_) => true,
+            Regex::Concat(items) | Regex::Intersect(items) => items.iter().all(Regex::is_nullable),
+            Regex::Alt(items) => items.iter().any(Regex::is_nullable),
+            Regex::Plus(inner) => inner.is_nullable(),
+            Regex::Repeat { inner, min, .. } => *min == 0 || inner.is_nullable(),
+            Regex::Complement(inner) => !inner.is_nullable(),
+        }
+    }
+
+    // The following constructors expect simplified parts and return a simplified regex
+
+    pub(crate) fn class(class: CharClass) -> Regex {
+        match (class.negated, class.ranges.as_slice()) {
+            (false, []) => Regex::Empty,
+            (false, &[(start, end)]) if start == end => Regex::Literal(start),
+            _ => Regex::Class(class),
+        }
+    }
+
+    pub(crate) fn concat(items: Vec<Regex>) -> Regex {
+        let mut result: Vec<Regex> = vec![];
+        for item in items.into_iter().flat_map(|item| match item {
+            Regex::Concat(items) => items,
+            item => vec![item],
+        }) {
+            match (result.last_mut(), item) {
+                (_, Regex::Epsilon) => (),
+                (_, Regex::Empty) => return Regex::Empty,
+                // r* r* = r*
+                (Some(Regex::Star(last)), Regex::Star(inner)) if *last == inner => (),
+                // r r* = r+
+                (Some(last), Regex::Star(inner)) if *last == *inner => *last = Regex::Plus(inner),
+                // r* r = r+
+                (Some(Regex::Star(last)), item) if **last == item => *result.last_mut().unwrap() = Regex::Plus(Box::new(item)),
+                (_, item) => result.push(item),
+            }
+        }
+
+        match result.len() {
+            0 => Regex::Epsilon,
+            1 => result.pop().unwrap(),
+            _ => Regex::Concat(result),
+        }
+    }
+
+    pub(crate) fn alt(items: Vec<Regex>) -> Regex {
+        let mut ranges = vec![];
+        let mut has_epsilon = false;
+        let mut result = vec![];
+        for item in items.into_iter().flat_map(|item| match item {
+            Regex::Alt(items) => items,
+            item => vec![item],
+        }) {
+            match item {
+                Regex::Empty => (),
+                Regex::Epsilon => has_epsilon = true,
+                Regex::Literal(symbol) => ranges.push((symbol, symbol)),
+                Regex::Class(class) if !class.negated => ranges.extend(class.ranges),
+                item => result.push(item),
+            }
+        }
+        if !ranges.is_empty() {
+            result.push(Self::class(CharClass::new(false, ranges)));
+        }
+        result.sort();
+        result.dedup();
+
+        let alt = match result.len() {
+            0 => Regex::Empty,
+            1 => result.pop().unwrap(),
+            _ => Regex::Alt(result),
+        };
+        // 1|r = r? when r doesn't already match the empty string
+        if has_epsilon { Self::optional(alt) } else { alt }
+    }
+
+    pub(crate) fn star(inner: Regex) -> Regex {
+        match inner {
+            Regex::Empty | Regex::Epsilon => Regex::Epsilon,
+            Regex::Star(inner) | Regex::Plus(inner) | Regex::Optional(inner) => Self::star(*inner),
+            // quantifiers inside the alternatives of a star are redundant
+            Regex::Alt(items) if items.iter().any(|item| matches!(item, Regex::Star(_) | Regex::Plus(_) | Regex::Optional(_))) => {
+                Self::star(Self::alt(items.into_iter().map(|item| match item {
+                    Regex::Star(inner) | Regex::Plus(inner) | Regex::Optional(inner) => *inner,
+                    item => item,
+                }).collect()))
+            },
+            inner => Regex::Star(Box::new(inner)),
+        }
+    }
+
+    pub(crate) fn plus(inner: Regex) -> Regex {
+        match inner {
+            Regex::Empty | Regex::Epsilon | Regex::Star(_) | Regex::Plus(_) => inner,
+            inner if inner.is_nullable() => Self::star(inner),
+            inner => Regex::Plus(Box::new(inner)),
+        }
+    }
+
+    pub(crate) fn optional(inner: Regex) -> Regex {
+        match inner {
+            Regex::Empty => Regex::Epsilon,
+            Regex::Plus(inner) => Self::star(*inner),
+            inner if inner.is_nullable() => inner,
+            inner => Regex::Optional(Box::new(inner)),
+        }
+    }
+
+    pub(crate) fn repeat(inner: Regex, min: usize, max: Option<usize>) -> Regex {
+        match (inner, min, max) {
+            (Regex::Epsilon, _, _) | (_, 0, Some(0)) => Regex::Epsilon,
+            (Regex::Empty, 0, _) => Regex::Epsilon,
+            (Regex::Empty, _, _) => Regex::Empty,
+            (inner, 1, Some(1)) => inner,
+            (inner, 0, Some(1)) => Self::optional(inner),
+            (inner, 0, None) => Self::star(inner),
+            (inner, 1, None) => Self::plus(inner),
+            (inner, min, max) => Regex::Repeat { inner: Box::new(inner), min, max },
+        }
+    }
+
+    pub(crate) fn intersect(parts: Vec<Regex>) -> Regex {
+        let mut result = vec![];
+        for part in parts.into_iter().flat_map(|part| match part {
+            Regex::Intersect(parts) => parts,
+            part => vec![part],
+        }) {
+            match part {
+                Regex::Empty => return Regex::Empty,
+                // ~0 matches every string, so it doesn't restrict the intersection
+                Regex::Complement(inner) if *inner == Regex::Empty => (),
+                part => result.push(part),
+            }
+        }
+        result.sort();
+        result.dedup();
+
+        match result.len() {
+            0 => Regex::Complement(Box::new(Regex::Empty)),
+            1 => result.pop().unwrap(),
+            _ => Regex::Intersect(result),
+        }
+    }
+
+    pub(crate) fn complement(inner: Regex) -> Regex {
+        match inner {
+            Regex::Complement(inner) => *inner,
+            inner => Regex::Complement(Box::new(inner)),
+        }
+    }
+}
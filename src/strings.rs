@@ -0,0 +1,136 @@
+use itertools::Itertools;
+
+use crate::{dfa::Dfa, nfa::Nfa};
+
+/*
+An iterator over the strings accepted by a DFA in shortlex order: shorter strings first,
+and strings of the same length in the order of their symbols.
+For a DFA in UTF-8 mode the symbols are bytes, see utf8::byte_symbol, and the length counts bytes.
+
+The strings of each length are found by a depth-first search that only enters states
+from which an accepting state can be reached by reading exactly the number of symbols that are left,
+so no time is spent on prefixes that can't be completed.
+*/
+pub struct Strings {
+    symbols: Vec<char>,
+    // transitions with the columns in the order of the sorted symbols
+    transition: Vec<Vec<usize>>,
+    marks: Vec<usize>,
+    reachable: Vec<bool>,
+    // completes[k][state] tells whether an accepting state can be reached from the state with exactly k symbols
+    completes: Vec<Vec<bool>>,
+    max_len: usize,
+    length: usize,
+    // the states on the path of the search, with the next column to try from each of them
+    stack: Vec<(usize, usize)>,
+    word: Vec<char>,
+}
+
+impl Strings {
+    fn new(dfa: &Dfa, max_len: usize) -> Self {
+        let alphabet = dfa.alphabet();
+        let order = (0..alphabet.len()).sorted_by_key(|&i| alphabet[i]).collect_vec();
+        let symbols = order.iter().map(|&i| alphabet[i]).collect();
+        let transition: Vec<Vec<usize>> = dfa.transition.iter().map(|row| order.iter().map(|&i| row[i]).collect()).collect();
+
+        let mut reachable = vec![false; transition.len()];
+        let mut stack = vec![0];
+        reachable[0] = true;
+        while let Some(state) = stack.pop() {
+            for &next in &transition[state] {
+                if !reachable[next] {
+                    reachable[next] = true;
+                    stack.push(next);
+                }
+            }
+        }
+
+        let completes = vec![dfa.marks.iter().map(|&mark| mark > 0).collect()];
+        Self { symbols, transition, marks: dfa.marks.clone(), reachable, completes, max_len, length: 0, stack: vec![], word: vec![] }
+    }
+
+    fn completes(&mut self, steps: usize, state: usize) -> bool {
+        while self.completes.len() <= steps {
+            let last = self.completes.last().unwrap();
+            let next = self.transition.iter().map(|row| row.iter().any(|&next| last[next])).collect();
+            self.completes.push(next);
+        }
+        self.completes[steps][state]
+    }
+
+    // Start the search for the strings of the current length, or go on to the next length if there are none
+    fn start_length(&mut self) -> bool {
+        while self.length <= self.max_len {
+            if self.completes(self.length, 0) {
+                self.stack.push((0, 0));
+                return true
+            }
+            // once no reachable state can complete a string of this length, there are no longer strings either
+            if !self.completes[self.length].iter().zip(&self.reachable).any(|(&completes, &reachable)| completes && reachable) {
+                self.max_len = self.length;
+            }
+            self.length += 1;
+        }
+        false
+    }
+}
+
+impl Iterator for Strings {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        loop {
+            if self.stack.is_empty() && !self.start_length() {
+                return None
+            }
+
+            let depth = self.stack.len() - 1;
+            let (state, column) = *self.stack.last().unwrap();
+            if depth == self.length {
+                // the search only reaches this depth in an accepting state
+                debug_assert!(self.marks[state] > 0);
+                let word = self.word.iter().collect();
+                self.stack.pop();
+                self.word.pop();
+                if self.stack.is_empty() {
+                    self.length += 1;
+                }
+                return Some(word)
+            }
+
+            let remaining = self.length - depth - 1;
+            let next_column = (column..self.symbols.len()).find(|&i| {
+                let next = self.transition[state][i];
+                self.completes(remaining, next)
+            });
+            match next_column {
+                Some(i) => {
+                    self.stack.last_mut().unwrap().1 = i + 1;
+                    self.stack.push((self.transition[state][i], 0));
+                    self.word.push(self.symbols[i]);
+                },
+                None => {
+                    self.stack.pop();
+                    self.word.pop();
+                    if self.stack.is_empty() {
+                        self.length += 1;
+                    }
+                },
+            }
+        }
+    }
+}
+
+impl Dfa {
+    // The strings accepted by the DFA with at most max_len symbols, in shortlex order, see Strings
+    pub fn strings(&self, max_len: usize) -> Strings {
+        Strings::new(self, max_len)
+    }
+}
+
+impl Nfa {
+    // The strings accepted by the NFA with at most max_len symbols, in shortlex order, by way of its DFA
+    pub fn strings(&self, max_len: usize) -> Strings {
+        Dfa::from_nfa(self).strings(max_len)
+    }
+}
@@ -0,0 +1,139 @@
+use itertools::Itertools;
+
+use crate::regex::{is_virtual, CharClass, Regex, LINE_END, LINE_START, TEXT_END};
+
+/*
+In UTF-8 mode automata read bytes instead of chars.
+A byte is represented by the char with the same code, U+0000 to U+00FF,
+so the rest of the pipeline can keep using chars as symbols.
+*/
+pub fn byte_symbol(byte: u8) -> char {
+    char::from(byte)
+}
+
+// The byte symbols of the UTF-8 encoding of a char. Virtual symbols are kept as they are, see regex::LINE_START
+pub fn encode(symbol: char) -> Vec<char> {
+    if is_virtual(symbol) {
+        return vec![symbol]
+    }
+    symbol.encode_utf8(&mut [0; 4]).bytes().map(byte_symbol).collect()
+}
+
+/*
+Split a range of chars into sequences of byte ranges, such that the UTF-8 encodings of the chars in the range
+are exactly the byte strings matched by one of the sequences.
+A sequence only covers chars whose encodings have the same length,
+and each of its positions is a single range of bytes.
+*/
+pub fn byte_sequences(start: char, end: char) -> Vec<Vec<(u8, u8)>> {
+    let mut sequences = vec![];
+    let mut stack = vec![(start as u32, end as u32)];
+
+    'ranges: while let Some((start, end)) = stack.pop() {
+        if start > end {
+            continue
+        }
+        // surrogates have no encoding
+        if start < 0xD800 && end > 0xDFFF {
+            stack.extend([(start, 0xD7FF), (0xE000, end)]);
+            continue
+        }
+        // split where the length of the encoding changes
+        for boundary in [0x7F, 0x7FF, 0xFFFF] {
+            if start <= boundary && end > boundary {
+                stack.extend([(start, boundary), (boundary + 1, end)]);
+                continue 'ranges
+            }
+        }
+        // split until every continuation byte of the start and end covers its whole range,
+        // unless the bytes before it are the same
+        let length = char::from_u32(start).unwrap().len_utf8() as u32;
+        for i in 1..length {
+            let mask = (1 << (6 * i)) - 1;
+            if start & !mask != end & !mask {
+                if start & mask != 0 {
+                    stack.extend([(start, start | mask), ((start | mask) + 1, end)]);
+                    continue 'ranges
+                }
+                if end & mask != mask {
+                    stack.extend([(start, (end & !mask) - 1), (end & !mask, end)]);
+                    continue 'ranges
+                }
+            }
+        }
+
+        let mut start_bytes = [0; 4];
+        let mut end_bytes = [0; 4];
+        let start_bytes = char::from_u32(start).unwrap().encode_utf8(&mut start_bytes).as_bytes();
+        let end_bytes = char::from_u32(end).unwrap().encode_utf8(&mut end_bytes).as_bytes();
+        sequences.push(start_bytes.iter().copied().zip(end_bytes.iter().copied()).collect());
+    }
+
+    sequences.sort();
+    sequences
+}
+
+impl Regex {
+    /*
+    Rewrite a regex over chars into an equivalent regex over the bytes of their UTF-8 encodings, see byte_symbol.
+    Literals become the sequence of their bytes and classes the alternatives of their byte_sequences.
+    A negated class is taken over all of Unicode instead of over the alphabet, so the wildcard matches any char.
+    The complement of a regex only contains valid UTF-8.
+    */
+    pub fn to_utf8(&self) -> Regex {
+        match self {
+            Regex::Empty | Regex::Epsilon => self.clone(),
+            Regex::Literal(symbol) => match encode(*symbol).as_slice() {
+                &[byte] => Regex::Literal(byte),
+                bytes => Regex::Concat(bytes.iter().copied().map(Regex::Literal).collect()),
+            },
+            Regex::Class(class) => Self::utf8_class(class),
+            Regex::Concat(items) => Regex::Concat(items.iter().map(Regex::to_utf8).collect()),
+            Regex::Alt(items) => Regex::Alt(items.iter().map(Regex::to_utf8).collect()),
+            Regex::Star(inner) => Regex::Star(Box::new(inner.to_utf8())),
+            Regex::Plus(inner) => Regex::Plus(Box::new(inner.to_utf8())),
+            Regex::Optional(inner) => Regex::Optional(Box::new(inner.to_utf8())),
+            Regex::Repeat { inner, min, max } => Regex::Repeat { inner: Box::new(inner.to_utf8()), min: *min, max: *max },
+            Regex::Intersect(parts) => Regex::Intersect(parts.iter().map(Regex::to_utf8).collect()),
+            Regex::Complement(inner) => {
+                let any_char = Self::utf8_class(&CharClass::new(true, vec![]));
+                Regex::Intersect(vec![Regex::Complement(Box::new(inner.to_utf8())), Regex::Star(Box::new(any_char))])
+            },
+        }
+    }
+
+    fn utf8_class(class: &CharClass) -> Regex {
+        // virtual symbols stay symbols of their own, the rest of the class is turned into bytes
+        let (before, after) = (char::from_u32(LINE_START as u32 - 1).unwrap(), char::from_u32(TEXT_END as u32 + 1).unwrap());
+        let sequences = class.positive_ranges().into_iter()
+            .flat_map(|(start, end)| {
+                if start > TEXT_END || end < LINE_START {
+                    return vec![(start, end)]
+                }
+                [(start, before), (after, end)].into_iter().filter(|(start, end)| start <= end).collect()
+            })
+            .flat_map(|(start, end)| byte_sequences(start, end))
+            .sorted()
+            .collect_vec();
+        let virtual_symbols = [LINE_START, LINE_END, TEXT_END].into_iter().filter(|&symbol| class.contains(symbol)).map(Regex::Literal);
+
+        match (Self::byte_trie(&sequences), virtual_symbols.collect_vec()) {
+            (bytes, virtual_symbols) if virtual_symbols.is_empty() => bytes,
+            (Regex::Alt(alternatives), virtual_symbols) => Regex::Alt([alternatives, virtual_symbols].concat()),
+            (bytes, virtual_symbols) => Regex::Alt([vec![bytes], virtual_symbols].concat()),
+        }
+    }
+
+    // The alternatives of sorted byte sequences, with the sequences that start with the same range sharing it
+    fn byte_trie(sequences: &[Vec<(u8, u8)>]) -> Regex {
+        let mut alternatives = vec![];
+        for group in sequences.chunk_by(|a, b| a[0] == b[0]) {
+            let (start, end) = group[0][0];
+            let first = Regex::Class(CharClass::new(false, vec![(byte_symbol(start), byte_symbol(end))]));
+            let rests = group.iter().map(|sequence| sequence[1..].to_vec()).filter(|rest| !rest.is_empty()).collect_vec();
+            alternatives.push(if rests.is_empty() { first } else { Regex::Concat(vec![first, Self::byte_trie(&rests)]) });
+        }
+
+        if alternatives.len() == 1 { alternatives.pop().unwrap() } else { Regex::Alt(alternatives) }
+    }
+}